@@ -1,18 +1,19 @@
 use serde_json::json;
 use std::error::Error;
-use swarmnode::{resources::agent_executor_job::AgentExecutorJob, set_config, SwarmNodeConfig};
+use swarmnode::{AgentId, ExecutorJobId, SwarmNodeClient, SwarmNodeConfig};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    // Set configuration
-    set_config(SwarmNodeConfig {
+    // Create a client bound to one account
+    let client = SwarmNodeClient::new(SwarmNodeConfig {
         api_key: Some("your_api_key".to_string()),
         api_base: None,
-    });
+        ..Default::default()
+    })?;
 
     // Example: List AgentExecutorJobs
     println!("Fetching agent executor jobs...");
-    match AgentExecutorJob::list(None).await {
+    match client.agent_executor_jobs().list(None).await {
         Ok(result) => {
             println!("AgentExecutorJobs fetched successfully:");
             for job in result.results {
@@ -32,7 +33,12 @@ async fn main() -> Result<(), Box<dyn Error>> {
             "FOO": "BAR"
         }
     });
-    match AgentExecutorJob::create("agent_id_example", Some(payload)).await {
+    let agent_id: AgentId = "agent_id_example".parse()?;
+    match client
+        .agent_executor_jobs()
+        .create_with_payload(&agent_id, &payload)
+        .await
+    {
         Ok(job) => {
             println!("AgentExecutorJob created successfully: {:?}", job);
         }
@@ -43,7 +49,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     // Example: Retrieve a specific AgentExecutorJob by ID
     println!("Retrieving an agent executor job...");
-    match AgentExecutorJob::retrieve("job_id_example").await {
+    let job_id: ExecutorJobId = "job_id_example".parse()?;
+    match client.agent_executor_jobs().retrieve(&job_id).await {
         Ok(job) => {
             println!("AgentExecutorJob retrieved successfully: {:?}", job);
         }
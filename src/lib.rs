@@ -1,17 +1,46 @@
 pub mod utils {
+    pub mod auth;
     pub mod client;
     pub mod config;
+    pub mod env_vars;
+    pub mod error;
+    pub mod id;
+    pub mod middleware;
     pub mod pagination;
+    pub mod patch;
+    pub mod query;
+    pub mod retry;
+    #[cfg(test)]
+    pub(crate) mod testing;
+    pub mod time;
+    pub(crate) mod trace;
 }
 
-pub use utils::config::{get_api_base, get_api_key, set_config, SwarmNodeConfig}; 
+pub use utils::auth::{AuthProvider, CommandKey, EnvVarKey, FileKey, StaticKey};
+pub use utils::client::{RequestOptions, SwarmNodeClient};
+pub use utils::config::{
+    get_api_base, get_api_key, set_config, Endpoint, HttpConfig, SwarmNodeConfig,
+};
+pub use utils::env_vars::EnvVars;
+pub use utils::error::{ApiError, SwarmNodeError};
+pub use utils::id::{
+    AgentId, BuildId, BuilderJobId, CronJobId, ExecutionId, ExecutorJobId, StoreId,
+};
+pub use utils::middleware::{
+    IncomingResponse, Middleware, OutgoingRequest, RequestKind, SyntheticResponse,
+};
+pub use utils::pagination::{CursorPaginatedResource, PagePaginatedResource, PageToken};
+pub use utils::patch::Patch;
+pub use utils::query::{ListQuery, PageNumbered};
+pub use utils::retry::RetryPolicy;
+pub use utils::time::Timestamp;
 
 pub mod resources {
+    pub mod agent;
+    pub mod agent_builder_job;
     pub mod agent_executor_cron_job;
     pub mod agent_executor_job;
-    pub mod agent_builder_job;
+    pub mod build;
     pub mod execution;
     pub mod store;
-    pub mod build;
-    pub mod agent;
 }
@@ -1,18 +1,185 @@
+use futures_util::{stream, Stream, TryStreamExt};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::collections::HashMap;
-use std::error::Error;
+use serde_json::{Map, Value};
+use std::time::Duration;
 
-use crate::utils::client::SwarmClient as Client;
-use crate::utils::pagination::PagePaginatedResource;
+use crate::utils::client::{RequestOptions, SwarmNodeClient};
+use crate::utils::env_vars::EnvVars;
+use crate::utils::error::SwarmNodeError;
+use crate::utils::id::{AgentId, StoreId};
+use crate::utils::pagination::{filters_of, PagePaginatedResource};
+use crate::utils::patch::{self, Patch};
+use crate::utils::query::{ListQuery, PageNumbered};
+use crate::utils::time::{self, Timestamp};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Agent {
-    pub id: String,
-    pub agent_id: String,
-    pub execution_address: String,
-    pub created: String,
+    pub id: AgentId,
+    pub name: String,
+    pub script: String,
+    // Contents of a requirements.txt file
+    #[serde(default)]
+    pub requirements: Option<String>,
+    #[serde(default)]
+    pub env_vars: Option<EnvVars>,
+    pub python_version: String,
+    #[serde(default)]
+    pub store_id: Option<StoreId>,
+    pub created: Timestamp,
+    // Fields returned by the API that this struct does not know about yet
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
+
+impl Agent {
+    // Time since the agent was created, `None` if `created` cannot be read
+    pub fn age(&self) -> Option<Duration> {
+        time::elapsed_since(&self.created)
+    }
+}
+
+// Body of `Agents::create`. Name, script, Python version and store are
+// required and checked before anything is sent.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AgentCreate {
+    pub name: Option<String>,
+    pub script: Option<String>,
+    pub python_version: Option<String>,
+    pub store_id: Option<StoreId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requirements: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env_vars: Option<EnvVars>,
+    // Sent as the `Idempotency-Key` header, not in the body
+    #[serde(skip)]
+    pub idempotency_key: Option<String>,
+}
+
+impl AgentCreate {
+    pub fn new() -> Self {
+        AgentCreate::default()
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn script(mut self, script: impl Into<String>) -> Self {
+        self.script = Some(script.into());
+        self
+    }
+
+    // Python version to run the script with, e.g. `3.12`
+    pub fn python_version(mut self, python_version: impl Into<String>) -> Self {
+        self.python_version = Some(python_version.into());
+        self
+    }
+
+    pub fn store_id(mut self, store_id: StoreId) -> Self {
+        self.store_id = Some(store_id);
+        self
+    }
+
+    pub fn requirements(mut self, requirements: impl Into<String>) -> Self {
+        self.requirements = Some(requirements.into());
+        self
+    }
+
+    pub fn env_vars(mut self, env_vars: EnvVars) -> Self {
+        self.env_vars = Some(env_vars);
+        self
+    }
+
+    // Idempotency key of the create, see `RequestOptions::for_create`
+    pub fn idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.idempotency_key = Some(key.into());
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), SwarmNodeError> {
+        patch::required("name", self.name.as_deref())?;
+        patch::required("script", self.script.as_deref())?;
+        patch::required("python_version", self.python_version.as_deref())?;
+        patch::required("store_id", self.store_id.as_ref().map(StoreId::as_str))
+    }
+}
+
+// Body of `Agents::update`. Fields left `Patch::Unchanged` are not sent; the
+// optional ones can also be cleared.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AgentUpdate {
+    #[serde(skip_serializing_if = "Patch::is_unchanged")]
+    pub name: Patch<String>,
+    #[serde(skip_serializing_if = "Patch::is_unchanged")]
+    pub script: Patch<String>,
+    #[serde(skip_serializing_if = "Patch::is_unchanged")]
+    pub python_version: Patch<String>,
+    #[serde(skip_serializing_if = "Patch::is_unchanged")]
+    pub store_id: Patch<StoreId>,
+    #[serde(skip_serializing_if = "Patch::is_unchanged")]
+    pub requirements: Patch<String>,
+    #[serde(skip_serializing_if = "Patch::is_unchanged")]
+    pub env_vars: Patch<EnvVars>,
+}
+
+impl AgentUpdate {
+    pub fn new() -> Self {
+        AgentUpdate::default()
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Patch::Set(name.into());
+        self
+    }
+
+    pub fn script(mut self, script: impl Into<String>) -> Self {
+        self.script = Patch::Set(script.into());
+        self
+    }
+
+    pub fn python_version(mut self, python_version: impl Into<String>) -> Self {
+        self.python_version = Patch::Set(python_version.into());
+        self
+    }
+
+    pub fn store_id(mut self, store_id: StoreId) -> Self {
+        self.store_id = Patch::Set(store_id);
+        self
+    }
+
+    pub fn clear_store_id(mut self) -> Self {
+        self.store_id = Patch::Clear;
+        self
+    }
+
+    pub fn requirements(mut self, requirements: impl Into<String>) -> Self {
+        self.requirements = Patch::Set(requirements.into());
+        self
+    }
+
+    pub fn clear_requirements(mut self) -> Self {
+        self.requirements = Patch::Clear;
+        self
+    }
+
+    pub fn env_vars(mut self, env_vars: EnvVars) -> Self {
+        self.env_vars = Patch::Set(env_vars);
+        self
+    }
+
+    pub fn clear_env_vars(mut self) -> Self {
+        self.env_vars = Patch::Clear;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), SwarmNodeError> {
+        patch::required_patch("name", &self.name)?;
+        patch::required_patch("script", &self.script)?;
+        patch::required_patch("python_version", &self.python_version)
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AgentList {
     pub next: Option<String>,
@@ -22,27 +189,53 @@ pub struct AgentList {
     pub current_page: u32,
 }
 
-impl Agent {
-    pub fn api_source() -> &'static str {
-        "agents"
+// Agent endpoints bound to a specific client, see `SwarmNodeClient::agents`
+pub struct Agents<'a> {
+    client: &'a SwarmNodeClient,
+    options: RequestOptions,
+}
+
+impl<'a> Agents<'a> {
+    pub fn new(client: &'a SwarmNodeClient) -> Self {
+        Agents {
+            client,
+            options: RequestOptions::default(),
+        }
+    }
+
+    // Total timeout for calls made through this handle, overriding the
+    // client's default
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
     }
 
     pub async fn list(
+        &self,
         page: Option<u32>,
-        page_size: Option<u8>,
-    ) -> Result<PagePaginatedResource<Agent>, Box<dyn Error>> {
-        let mut params = HashMap::new();
-        params.insert("page".to_string(), page.unwrap_or(1).to_string());
-        params.insert("page_size".to_string(), page_size.unwrap_or(10).to_string());
-
-        let response = Client::request_action::<AgentList>(
-            "GET",
-            &format!("{}/", Self::api_source()),
-            Some(params),
-            None,
-        )
-        .await
-        .map_err(|e| format!("Error listing Agents: {}", e))?;
+        page_size: Option<u32>,
+    ) -> Result<PagePaginatedResource<Agent>, SwarmNodeError> {
+        let query = ListQuery::<Agent>::paged(page, page_size);
+        self.list_with(&query).await
+    }
+
+    // Agents matching `query`
+    pub async fn list_with(
+        &self,
+        query: &ListQuery<Agent>,
+    ) -> Result<PagePaginatedResource<Agent>, SwarmNodeError> {
+        let params = query.page_params(self.client.max_page_size())?;
+        let filters = filters_of(&params);
+        let response = self
+            .client
+            .request_action::<AgentList, _>(
+                "GET",
+                &format!("{}/", Agent::api_source()),
+                Some(params),
+                None::<&Value>,
+                &self.options,
+            )
+            .await?;
 
         Ok(PagePaginatedResource {
             next_url: response.next,
@@ -50,107 +243,191 @@ impl Agent {
             results: response.results,
             total_count: response.total_count,
             current_page: response.current_page,
-            resource_class: "Agent",
+            resource_class: "Agent".to_string(),
+            filters,
+            client: self.client.clone(),
         })
     }
 
-    pub async fn retrieve(id: &str) -> Result<Agent, Box<dyn Error>> {
-        let agent_executor_cron_job = Client::request_action::<Agent>(
-            "GET",
-            &format!("{}/{}/", Self::api_source(), id),
-            None,
-            None,
-        )
-        .await
-        .map_err(|e| format!("Error retrieving Agent: {}", e))?;
-
-        Ok(agent_executor_cron_job)
-    }
-
-    pub async fn create(
-        name: &str,
-        script: &str,
-        python_version: &str,
-        store_id: &str,
-        requirements: Option<&str>,
-        env_vars: Option<&str>,
-    ) -> Result<Agent, Box<dyn Error>> {
-        let mut data = HashMap::new();
-        data.insert("name".to_string(), name.to_string());
-        data.insert("script".to_string(), script.to_string());
-        data.insert("python_version".to_string(), python_version.to_string());
-        data.insert("store_id".to_string(), store_id.to_string());
-        if let Some(requirements) = requirements {
-            data.insert("requirements".to_string(), requirements.to_string());
-        }
-        if let Some(env_vars) = env_vars {
-            data.insert("env_vars".to_string(), env_vars.to_string());
-        }
+    // Every agent, fetching pages lazily as the stream is polled
+    pub fn list_all(
+        &self,
+        page_size: Option<u32>,
+    ) -> impl Stream<Item = Result<Agent, SwarmNodeError>> + '_ {
+        stream::once(self.list(Some(1), page_size))
+            .map_ok(PagePaginatedResource::into_stream)
+            .try_flatten()
+    }
 
-        let agent = Client::request_action::<Agent>(
-            "POST",
-            &format!("{}/create/", Self::api_source()),
-            None,
-            Some(data),
-        )
-        .await
-        .map_err(|e| format!("Error creating Agent: {}", e))?;
+    pub async fn retrieve(&self, id: &AgentId) -> Result<Agent, SwarmNodeError> {
+        let agent = self
+            .client
+            .request_action::<Agent, _>(
+                "GET",
+                &format!("{}/{}/", Agent::api_source(), id),
+                None,
+                None::<&Value>,
+                &self.options,
+            )
+            .await?;
+
+        Ok(agent)
+    }
+
+    pub async fn create(&self, agent: &AgentCreate) -> Result<Agent, SwarmNodeError> {
+        agent.validate()?;
+
+        let agent = self
+            .client
+            .request_action::<Agent, _>(
+                "POST",
+                &format!("{}/create/", Agent::api_source()),
+                None,
+                Some(agent),
+                &self.options.for_create(agent.idempotency_key.as_deref()),
+            )
+            .await?;
 
         Ok(agent)
     }
 
     pub async fn update(
-        id: &str,
-        name: Option<&str>,
-        script: Option<&str>,
-        python_version: Option<&str>,
-        store_id: Option<&str>,
-        requirements: Option<&str>,
-        env_vars: Option<&str>,
-    ) -> Result<Agent, Box<dyn Error>> {
-        let mut data = HashMap::new();
-        if let Some(name) = name {
-            data.insert("name".to_string(), name.to_string());
-        }
-        if let Some(script) = script {
-            data.insert("script".to_string(), script.to_string());
-        }
-        if let Some(python_version) = python_version {
-            data.insert("python_version".to_string(), python_version.to_string());
-        }
-        if let Some(store_id) = store_id {
-            data.insert("store_id".to_string(), store_id.to_string());
-        }
-        if let Some(requirements) = requirements {
-            data.insert("requirements".to_string(), requirements.to_string());
-        }
-        if let Some(env_vars) = env_vars {
-            data.insert("env_vars".to_string(), env_vars.to_string());
-        }
+        &self,
+        id: &AgentId,
+        update: &AgentUpdate,
+    ) -> Result<Agent, SwarmNodeError> {
+        update.validate()?;
 
-        let agent = Client::request_action::<Agent>(
-            "PATCH",
-            &format!("{}/{}/update/", Self::api_source(), id),
-            None,
-            Some(data),
-        )
-        .await
-        .map_err(|e| format!("Error updating Agent: {}", e))?;
+        let agent = self
+            .client
+            .request_action::<Agent, _>(
+                "PATCH",
+                &format!("{}/{}/update/", Agent::api_source(), id),
+                None,
+                Some(update),
+                &self.options,
+            )
+            .await?;
 
         Ok(agent)
     }
 
-    pub async fn delete(id: &str) -> Result<(), Box<dyn Error>> {
-        Client::request_action::<Value>(
-            "DELETE",
-            &format!("{}/{}/delete/", Self::api_source(), id),
-            None,
-            None,
-        )
-        .await
-        .map_err(|e| format!("Error deleting Agent: {}", e))?;
+    pub async fn delete(&self, id: &AgentId) -> Result<(), SwarmNodeError> {
+        self.client
+            .request_action::<Value, _>(
+                "DELETE",
+                &format!("{}/{}/delete/", Agent::api_source(), id),
+                None,
+                None::<&Value>,
+                &self.options,
+            )
+            .await?;
 
         Ok(())
     }
+}
+
+// Shorthands over the client configured with `set_config`
+impl Agent {
+    pub fn api_source() -> &'static str {
+        "agents"
+    }
 
+    pub async fn list(
+        page: Option<u32>,
+        page_size: Option<u32>,
+    ) -> Result<PagePaginatedResource<Agent>, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .agents()
+            .list(page, page_size)
+            .await
+    }
+
+    pub fn list_all(page_size: Option<u32>) -> impl Stream<Item = Result<Agent, SwarmNodeError>> {
+        stream::once(async move {
+            SwarmNodeClient::global()?
+                .agents()
+                .list(Some(1), page_size)
+                .await
+        })
+        .map_ok(PagePaginatedResource::into_stream)
+        .try_flatten()
+    }
+
+    pub async fn list_with(
+        query: &ListQuery<Agent>,
+    ) -> Result<PagePaginatedResource<Agent>, SwarmNodeError> {
+        SwarmNodeClient::global()?.agents().list_with(query).await
+    }
+
+    pub async fn retrieve(id: &AgentId) -> Result<Agent, SwarmNodeError> {
+        SwarmNodeClient::global()?.agents().retrieve(id).await
+    }
+
+    pub async fn create(agent: &AgentCreate) -> Result<Agent, SwarmNodeError> {
+        SwarmNodeClient::global()?.agents().create(agent).await
+    }
+
+    pub async fn update(id: &AgentId, update: &AgentUpdate) -> Result<Agent, SwarmNodeError> {
+        SwarmNodeClient::global()?.agents().update(id, update).await
+    }
+
+    pub async fn delete(id: &AgentId) -> Result<(), SwarmNodeError> {
+        SwarmNodeClient::global()?.agents().delete(id).await
+    }
+}
+
+impl PageNumbered for Agent {}
+
+// Filters of `Agent::list_with`
+impl ListQuery<Agent> {
+    // Only agents whose name contains `name`
+    pub fn search(self, name: impl Into<String>) -> Self {
+        self.param("search", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_sends_only_what_changed() {
+        let update = AgentUpdate::new().name("x").clear_requirements();
+        assert_eq!(
+            serde_json::to_string(&update).unwrap(),
+            r#"{"name":"x","requirements":null}"#
+        );
+        assert_eq!(serde_json::to_string(&AgentUpdate::new()).unwrap(), "{}");
+    }
+
+    #[test]
+    fn update_cannot_clear_or_blank_required_fields() {
+        assert!(AgentUpdate::new().clear_store_id().validate().is_ok());
+        assert!(AgentUpdate::new().name(" ").validate().is_err());
+
+        let update = AgentUpdate {
+            script: Patch::Clear,
+            ..AgentUpdate::new()
+        };
+        assert!(update.validate().is_err());
+    }
+
+    #[test]
+    fn create_reports_missing_fields() {
+        let message = |agent: &AgentCreate| agent.validate().unwrap_err().to_string();
+
+        let agent = AgentCreate::new();
+        assert!(message(&agent).contains("name is required"));
+
+        let agent = agent.name("agent").script("def main(request, store): pass");
+        assert!(message(&agent).contains("python_version is required"));
+
+        let agent = agent.python_version("3.12");
+        assert!(message(&agent).contains("store_id is required"));
+
+        let agent = agent.store_id("s1".parse().unwrap());
+        assert!(agent.validate().is_ok());
+        assert!(message(&agent.clone().name("")).contains("name must not be empty"));
+    }
 }
@@ -1,17 +1,30 @@
+use futures_util::{stream, Stream, TryStreamExt};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::error::Error;
+use serde_json::Value;
+use std::time::Duration;
 
-use crate::utils::client::SwarmClient as Client;
-use crate::utils::pagination::PagePaginatedResource;
+use crate::utils::client::{RequestOptions, SwarmNodeClient};
+use crate::utils::error::SwarmNodeError;
+use crate::utils::id::{AgentId, BuilderJobId};
+use crate::utils::pagination::{filters_of, PagePaginatedResource};
+use crate::utils::query::{ListQuery, PageNumbered};
+use crate::utils::time::{self, Timestamp};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AgentBuilderJob {
-    pub id: String,
-    pub agent_id: String,
+    pub id: BuilderJobId,
+    pub agent_id: AgentId,
     pub execution_address: String,
-    pub created: String,
+    pub created: Timestamp,
 }
+
+impl AgentBuilderJob {
+    // Time since the builder job was created, `None` if `created` cannot be read
+    pub fn age(&self) -> Option<Duration> {
+        time::elapsed_since(&self.created)
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AgentBuilderJobList {
     pub next: Option<String>,
@@ -21,31 +34,57 @@ pub struct AgentBuilderJobList {
     pub current_page: u32,
 }
 
-impl AgentBuilderJob {
-    pub fn api_source() -> &'static str {
-        "agent-builder-jobs"
+// AgentBuilderJob endpoints bound to a specific client
+pub struct AgentBuilderJobs<'a> {
+    client: &'a SwarmNodeClient,
+    options: RequestOptions,
+}
+
+impl<'a> AgentBuilderJobs<'a> {
+    pub fn new(client: &'a SwarmNodeClient) -> Self {
+        AgentBuilderJobs {
+            client,
+            options: RequestOptions::default(),
+        }
+    }
+
+    // Total timeout for calls made through this handle, overriding the
+    // client's default
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
     }
 
     pub async fn list(
-        agent_id: Option<String>,
+        &self,
+        agent_id: Option<AgentId>,
         page: Option<u32>,
-        page_size: Option<u8>,
-    ) -> Result<PagePaginatedResource<AgentBuilderJob>, Box<dyn Error>> {
-        let mut params = HashMap::new();
-        params.insert("page".to_string(), page.unwrap_or(1).to_string());
-        params.insert("page_size".to_string(), page_size.unwrap_or(10).to_string());
+        page_size: Option<u32>,
+    ) -> Result<PagePaginatedResource<AgentBuilderJob>, SwarmNodeError> {
+        let mut query = ListQuery::<AgentBuilderJob>::paged(page, page_size);
         if let Some(agent_id) = agent_id {
-            params.insert("agent_id".to_string(), agent_id);
+            query = query.agent_id(&agent_id);
         }
+        self.list_with(&query).await
+    }
 
-        let response = Client::request_action::<AgentBuilderJobList>(
-            "GET",
-            &format!("{}/", Self::api_source()),
-            Some(params),
-            None,
-        )
-        .await
-        .map_err(|e| format!("Error listing AgentBuilderJobs: {}", e))?;
+    // Builder jobs matching `query`
+    pub async fn list_with(
+        &self,
+        query: &ListQuery<AgentBuilderJob>,
+    ) -> Result<PagePaginatedResource<AgentBuilderJob>, SwarmNodeError> {
+        let params = query.page_params(self.client.max_page_size())?;
+        let filters = filters_of(&params);
+        let response = self
+            .client
+            .request_action::<AgentBuilderJobList, _>(
+                "GET",
+                &format!("{}/", AgentBuilderJob::api_source()),
+                Some(params),
+                None::<&Value>,
+                &self.options,
+            )
+            .await?;
 
         Ok(PagePaginatedResource {
             next_url: response.next,
@@ -53,20 +92,93 @@ impl AgentBuilderJob {
             results: response.results,
             total_count: response.total_count,
             current_page: response.current_page,
-            resource_class: "AgentBuilderJob",
+            resource_class: "AgentBuilderJob".to_string(),
+            filters,
+            client: self.client.clone(),
+        })
+    }
+
+    // Every AgentBuilderJob matching the filters, fetching pages lazily as the
+    // stream is polled
+    pub fn list_all(
+        &self,
+        agent_id: Option<AgentId>,
+        page_size: Option<u32>,
+    ) -> impl Stream<Item = Result<AgentBuilderJob, SwarmNodeError>> + '_ {
+        stream::once(self.list(agent_id, Some(1), page_size))
+            .map_ok(PagePaginatedResource::into_stream)
+            .try_flatten()
+    }
+
+    pub async fn retrieve(&self, id: &BuilderJobId) -> Result<AgentBuilderJob, SwarmNodeError> {
+        let agent_builder_job = self
+            .client
+            .request_action::<AgentBuilderJob, _>(
+                "GET",
+                &format!("{}/{}/", AgentBuilderJob::api_source(), id),
+                None,
+                None::<&Value>,
+                &self.options,
+            )
+            .await?;
+
+        Ok(agent_builder_job)
+    }
+}
+
+// Shorthands over the client configured with `set_config`
+impl AgentBuilderJob {
+    pub fn api_source() -> &'static str {
+        "agent-builder-jobs"
+    }
+
+    pub async fn list(
+        agent_id: Option<AgentId>,
+        page: Option<u32>,
+        page_size: Option<u32>,
+    ) -> Result<PagePaginatedResource<AgentBuilderJob>, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .agent_builder_jobs()
+            .list(agent_id, page, page_size)
+            .await
+    }
+
+    pub fn list_all(
+        agent_id: Option<AgentId>,
+        page_size: Option<u32>,
+    ) -> impl Stream<Item = Result<AgentBuilderJob, SwarmNodeError>> {
+        stream::once(async move {
+            SwarmNodeClient::global()?
+                .agent_builder_jobs()
+                .list(agent_id, Some(1), page_size)
+                .await
         })
+        .map_ok(PagePaginatedResource::into_stream)
+        .try_flatten()
+    }
+
+    pub async fn list_with(
+        query: &ListQuery<AgentBuilderJob>,
+    ) -> Result<PagePaginatedResource<AgentBuilderJob>, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .agent_builder_jobs()
+            .list_with(query)
+            .await
     }
 
-    pub async fn retrieve(id: &str) -> Result<AgentBuilderJob, Box<dyn Error>> {
-        let agent_executor_cron_job = Client::request_action::<AgentBuilderJob>(
-            "GET",
-            &format!("{}/{}/", Self::api_source(), id),
-            None,
-            None,
-        )
-        .await
-        .map_err(|e| format!("Error retrieving AgentBuilderJob: {}", e))?;
-
-        Ok(agent_executor_cron_job)
+    pub async fn retrieve(id: &BuilderJobId) -> Result<AgentBuilderJob, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .agent_builder_jobs()
+            .retrieve(id)
+            .await
+    }
+}
+
+impl PageNumbered for AgentBuilderJob {}
+
+// Filters of `AgentBuilderJob::list_with`
+impl ListQuery<AgentBuilderJob> {
+    pub fn agent_id(self, agent_id: &AgentId) -> Self {
+        self.param("agent_id", agent_id.as_str())
     }
 }
@@ -1,18 +1,155 @@
+use futures_util::{future, stream, Stream, TryStreamExt};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
-use std::error::Error;
+use std::fmt;
+use std::time::Duration;
 
-use crate::utils::client::SwarmClient as Client;
-use crate::utils::pagination::PagePaginatedResource;
+use crate::utils::client::{RequestOptions, SwarmNodeClient};
+use crate::utils::error::SwarmNodeError;
+use crate::utils::id::{AgentId, CronJobId};
+use crate::utils::pagination::{filters_of, PagePaginatedResource};
+use crate::utils::patch::{self, Patch};
+use crate::utils::query::{ListQuery, PageNumbered};
+use crate::utils::time::{self, Timestamp};
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum CronJobStatus {
+    Running,
+    Suspended,
+    // A status added to the API after this version of the crate
+    #[serde(other)]
+    Unknown,
+}
+
+impl CronJobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CronJobStatus::Running => "running",
+            CronJobStatus::Suspended => "suspended",
+            CronJobStatus::Unknown => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for CronJobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AgentExecutorCronJob {
-    pub id: String,
-    pub agent_id: String,
-    pub execution_address: String,
-    pub created: String,
+    pub id: CronJobId,
+    pub agent_id: AgentId,
+    pub name: String,
+    // Cron expression of the schedule, e.g. `*/5 * * * *`
+    pub expression: String,
+    pub status: CronJobStatus,
+    // Address to listen on for the executions the schedule starts, see
+    // `SwarmNodeClient::listen_to_execution_stream`
+    pub execution_stream_address: String,
+    pub created: Timestamp,
+}
+
+impl AgentExecutorCronJob {
+    // Time since the schedule was created, `None` if `created` cannot be read
+    pub fn age(&self) -> Option<Duration> {
+        time::elapsed_since(&self.created)
+    }
+}
+
+// Body of `AgentExecutorCronJobs::create`, every field is required
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CronJobCreate {
+    pub agent_id: Option<AgentId>,
+    pub name: Option<String>,
+    pub expression: Option<String>,
+    // Sent as the `Idempotency-Key` header, not in the body
+    #[serde(skip)]
+    pub idempotency_key: Option<String>,
+}
+
+impl CronJobCreate {
+    pub fn new() -> Self {
+        CronJobCreate::default()
+    }
+
+    pub fn agent_id(mut self, agent_id: AgentId) -> Self {
+        self.agent_id = Some(agent_id);
+        self
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn expression(mut self, expression: impl Into<String>) -> Self {
+        self.expression = Some(expression.into());
+        self
+    }
+
+    // Idempotency key of the create, see `RequestOptions::for_create`
+    pub fn idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.idempotency_key = Some(key.into());
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), SwarmNodeError> {
+        patch::required("agent_id", self.agent_id.as_ref().map(AgentId::as_str))?;
+        patch::required("name", self.name.as_deref())?;
+        patch::required("expression", self.expression.as_deref())
+    }
+}
+
+// Body of `AgentExecutorCronJobs::update`. Fields left `Patch::Unchanged`
+// are not sent.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CronJobUpdate {
+    #[serde(skip_serializing_if = "Patch::is_unchanged")]
+    pub name: Patch<String>,
+    #[serde(skip_serializing_if = "Patch::is_unchanged")]
+    pub expression: Patch<String>,
+    #[serde(skip_serializing_if = "Patch::is_unchanged")]
+    pub status: Patch<CronJobStatus>,
+}
+
+impl CronJobUpdate {
+    pub fn new() -> Self {
+        CronJobUpdate::default()
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Patch::Set(name.into());
+        self
+    }
+
+    pub fn expression(mut self, expression: impl Into<String>) -> Self {
+        self.expression = Patch::Set(expression.into());
+        self
+    }
+
+    pub fn status(mut self, status: CronJobStatus) -> Self {
+        self.status = Patch::Set(status);
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), SwarmNodeError> {
+        patch::required_patch("name", &self.name)?;
+        patch::required_patch("expression", &self.expression)?;
+        match self.status {
+            Patch::Clear => Err(SwarmNodeError::InvalidArgument(
+                "status cannot be cleared".to_string(),
+            )),
+            Patch::Set(CronJobStatus::Unknown) => Err(SwarmNodeError::InvalidArgument(
+                "status must be running or suspended".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
 }
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AgentExecutorCronJobList {
     pub next: Option<String>,
@@ -22,31 +159,57 @@ pub struct AgentExecutorCronJobList {
     pub current_page: u32,
 }
 
-impl AgentExecutorCronJob {
-    pub fn api_source() -> &'static str {
-        "agent-executor-cron-jobs"
+// AgentExecutorCronJob endpoints bound to a specific client
+pub struct AgentExecutorCronJobs<'a> {
+    client: &'a SwarmNodeClient,
+    options: RequestOptions,
+}
+
+impl<'a> AgentExecutorCronJobs<'a> {
+    pub fn new(client: &'a SwarmNodeClient) -> Self {
+        AgentExecutorCronJobs {
+            client,
+            options: RequestOptions::default(),
+        }
+    }
+
+    // Total timeout for calls made through this handle, overriding the
+    // client's default
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
     }
 
     pub async fn list(
-        agent_id: Option<String>,
+        &self,
+        agent_id: Option<AgentId>,
         page: Option<u32>,
-        page_size: Option<u8>,
-    ) -> Result<PagePaginatedResource<AgentExecutorCronJob>, Box<dyn Error>> {
-        let mut params = HashMap::new();
-        params.insert("page".to_string(), page.unwrap_or(1).to_string());
-        params.insert("page_size".to_string(), page_size.unwrap_or(10).to_string());
+        page_size: Option<u32>,
+    ) -> Result<PagePaginatedResource<AgentExecutorCronJob>, SwarmNodeError> {
+        let mut query = ListQuery::<AgentExecutorCronJob>::paged(page, page_size);
         if let Some(agent_id) = agent_id {
-            params.insert("agent_id".to_string(), agent_id);
+            query = query.agent_id(&agent_id);
         }
+        self.list_with(&query).await
+    }
 
-        let response = Client::request_action::<AgentExecutorCronJobList>(
-            "GET",
-            &format!("{}/", Self::api_source()),
-            Some(params),
-            None,
-        )
-        .await
-        .map_err(|e| format!("Error listing AgentExecutorCronJobs: {}", e))?;
+    // Cron jobs matching `query`
+    pub async fn list_with(
+        &self,
+        query: &ListQuery<AgentExecutorCronJob>,
+    ) -> Result<PagePaginatedResource<AgentExecutorCronJob>, SwarmNodeError> {
+        let params = query.page_params(self.client.max_page_size())?;
+        let filters = filters_of(&params);
+        let response = self
+            .client
+            .request_action::<AgentExecutorCronJobList, _>(
+                "GET",
+                &format!("{}/", AgentExecutorCronJob::api_source()),
+                Some(params),
+                None::<&Value>,
+                &self.options,
+            )
+            .await?;
 
         Ok(PagePaginatedResource {
             next_url: response.next,
@@ -54,83 +217,248 @@ impl AgentExecutorCronJob {
             results: response.results,
             total_count: response.total_count,
             current_page: response.current_page,
-            resource_class: "AgentExecutorCronJob",
+            resource_class: "AgentExecutorCronJob".to_string(),
+            filters,
+            client: self.client.clone(),
         })
     }
 
-    pub async fn retrieve(id: &str) -> Result<AgentExecutorCronJob, Box<dyn Error>> {
-        let agent_executor_cron_job = Client::request_action::<AgentExecutorCronJob>(
-            "GET",
-            &format!("{}/{}/", Self::api_source(), id),
-            None,
-            None,
-        )
-        .await
-        .map_err(|e| format!("Error retrieving AgentExecutorCronJob: {}", e))?;
+    // Every AgentExecutorCronJob matching the filters, fetching pages lazily as the
+    // stream is polled
+    pub fn list_all(
+        &self,
+        agent_id: Option<AgentId>,
+        page_size: Option<u32>,
+    ) -> impl Stream<Item = Result<AgentExecutorCronJob, SwarmNodeError>> + '_ {
+        stream::once(self.list(agent_id, Some(1), page_size))
+            .map_ok(PagePaginatedResource::into_stream)
+            .try_flatten()
+    }
+
+    pub async fn retrieve(&self, id: &CronJobId) -> Result<AgentExecutorCronJob, SwarmNodeError> {
+        let agent_executor_cron_job = self
+            .client
+            .request_action::<AgentExecutorCronJob, _>(
+                "GET",
+                &format!("{}/{}/", AgentExecutorCronJob::api_source(), id),
+                None,
+                None::<&Value>,
+                &self.options,
+            )
+            .await?;
 
         Ok(agent_executor_cron_job)
     }
 
     pub async fn create(
-        agent_id: &str,
-        name: &str,
-        expression: &str,
-    ) -> Result<AgentExecutorCronJob, Box<dyn Error>> {
-        let mut data = HashMap::new();
-        data.insert("agent_id".to_string(), agent_id.to_string());
-        data.insert("name".to_string(), name.to_string());
-        data.insert("expression".to_string(), expression.to_string());
-
-        let agent_executor_cron_job = Client::request_action::<AgentExecutorCronJob>(
-            "POST",
-            &format!("{}/create/", Self::api_source()),
-            None,
-            Some(data),
-        )
-        .await
-        .map_err(|e| format!("Error creating AgentExecutorCronJob: {}", e))?;
+        &self,
+        cron_job: &CronJobCreate,
+    ) -> Result<AgentExecutorCronJob, SwarmNodeError> {
+        cron_job.validate()?;
+
+        let agent_executor_cron_job = self
+            .client
+            .request_action::<AgentExecutorCronJob, _>(
+                "POST",
+                &format!("{}/create/", AgentExecutorCronJob::api_source()),
+                None,
+                Some(cron_job),
+                &self.options.for_create(cron_job.idempotency_key.as_deref()),
+            )
+            .await?;
 
         Ok(agent_executor_cron_job)
     }
 
     pub async fn update(
-        id: &str,
-        name: Option<&str>,
-        payload: Option<HashMap<String, Value>>,
-    ) -> Result<AgentExecutorCronJob, Box<dyn Error>> {
-        let mut data = HashMap::new();
-        if let Some(name) = name {
-            data.insert("name".to_string(), name.to_string());
+        &self,
+        id: &CronJobId,
+        update: &CronJobUpdate,
+    ) -> Result<AgentExecutorCronJob, SwarmNodeError> {
+        update.validate()?;
+
+        let agent_executor_cron_job = self
+            .client
+            .request_action::<AgentExecutorCronJob, _>(
+                "PATCH",
+                &format!("{}/{}/update/", AgentExecutorCronJob::api_source(), id),
+                None,
+                Some(update),
+                &self.options,
+            )
+            .await?;
+
+        Ok(agent_executor_cron_job)
+    }
+
+    // Suspend the schedule, no executions start until `resume`
+    pub async fn pause(&self, id: &CronJobId) -> Result<AgentExecutorCronJob, SwarmNodeError> {
+        self.update(id, &CronJobUpdate::new().status(CronJobStatus::Suspended))
+            .await
+    }
+
+    pub async fn resume(&self, id: &CronJobId) -> Result<AgentExecutorCronJob, SwarmNodeError> {
+        self.update(id, &CronJobUpdate::new().status(CronJobStatus::Running))
+            .await
+    }
+
+    // Suspend every running schedule, of one agent or of all of them, and
+    // return the jobs that were suspended so they can be resumed later.
+    // Stops at the first failure; jobs suspended before it stay suspended.
+    pub async fn pause_all(
+        &self,
+        agent_id: Option<AgentId>,
+    ) -> Result<Vec<AgentExecutorCronJob>, SwarmNodeError> {
+        let mut query = ListQuery::<AgentExecutorCronJob>::new().status(CronJobStatus::Running);
+        if let Some(max_page_size) = self.client.max_page_size() {
+            query = query.page_size(max_page_size);
         }
-        if let Some(payload) = payload {
-            for (key, value) in payload {
-                data.insert(key, serde_json::to_string(&value).unwrap());
-            }
+        if let Some(agent_id) = agent_id {
+            query = query.agent_id(&agent_id);
         }
 
-        let agent_executor_cron_job = Client::request_action::<AgentExecutorCronJob>(
-            "PATCH",
-            &format!("{}/{}/update/", Self::api_source(), id),
-            None,
-            Some(data),
-        )
-        .await
-        .map_err(|e| format!("Error updating AgentExecutorCronJob: {}", e))?;
+        // Collect first, suspending jobs while paging would shift the pages.
+        // The status is checked here too in case the API ignores the filter:
+        // jobs someone suspended must not end up in the list to resume.
+        let running: Vec<_> = self
+            .list_with(&query)
+            .await?
+            .into_stream()
+            .try_filter(|job| future::ready(job.status == CronJobStatus::Running))
+            .try_collect()
+            .await?;
 
-        Ok(agent_executor_cron_job)
+        let mut paused = Vec::with_capacity(running.len());
+        for job in running {
+            paused.push(self.pause(&job.id).await?);
+        }
+        Ok(paused)
     }
 
-    pub async fn delete(id: &str) -> Result<(), Box<dyn Error>> {
-        Client::request_action::<Value>(
-            "DELETE",
-            &format!("{}/{}/delete/", Self::api_source(), id),
-            None,
-            None,
-        )
-        .await
-        .map_err(|e| format!("Error deleting AgentExecutorCronJob: {}", e))?;
+    pub async fn delete(&self, id: &CronJobId) -> Result<(), SwarmNodeError> {
+        self.client
+            .request_action::<Value, _>(
+                "DELETE",
+                &format!("{}/{}/delete/", AgentExecutorCronJob::api_source(), id),
+                None,
+                None::<&Value>,
+                &self.options,
+            )
+            .await?;
 
         Ok(())
     }
+}
+
+// Shorthands over the client configured with `set_config`
+impl AgentExecutorCronJob {
+    pub fn api_source() -> &'static str {
+        "agent-executor-cron-jobs"
+    }
+
+    pub async fn list(
+        agent_id: Option<AgentId>,
+        page: Option<u32>,
+        page_size: Option<u32>,
+    ) -> Result<PagePaginatedResource<AgentExecutorCronJob>, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .agent_executor_cron_jobs()
+            .list(agent_id, page, page_size)
+            .await
+    }
+
+    pub fn list_all(
+        agent_id: Option<AgentId>,
+        page_size: Option<u32>,
+    ) -> impl Stream<Item = Result<AgentExecutorCronJob, SwarmNodeError>> {
+        stream::once(async move {
+            SwarmNodeClient::global()?
+                .agent_executor_cron_jobs()
+                .list(agent_id, Some(1), page_size)
+                .await
+        })
+        .map_ok(PagePaginatedResource::into_stream)
+        .try_flatten()
+    }
+
+    pub async fn list_with(
+        query: &ListQuery<AgentExecutorCronJob>,
+    ) -> Result<PagePaginatedResource<AgentExecutorCronJob>, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .agent_executor_cron_jobs()
+            .list_with(query)
+            .await
+    }
+
+    pub async fn retrieve(id: &CronJobId) -> Result<AgentExecutorCronJob, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .agent_executor_cron_jobs()
+            .retrieve(id)
+            .await
+    }
+
+    pub async fn create(cron_job: &CronJobCreate) -> Result<AgentExecutorCronJob, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .agent_executor_cron_jobs()
+            .create(cron_job)
+            .await
+    }
+
+    pub async fn update(
+        id: &CronJobId,
+        update: &CronJobUpdate,
+    ) -> Result<AgentExecutorCronJob, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .agent_executor_cron_jobs()
+            .update(id, update)
+            .await
+    }
+
+    pub async fn pause(id: &CronJobId) -> Result<AgentExecutorCronJob, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .agent_executor_cron_jobs()
+            .pause(id)
+            .await
+    }
 
+    pub async fn resume(id: &CronJobId) -> Result<AgentExecutorCronJob, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .agent_executor_cron_jobs()
+            .resume(id)
+            .await
+    }
+
+    pub async fn pause_all(
+        agent_id: Option<AgentId>,
+    ) -> Result<Vec<AgentExecutorCronJob>, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .agent_executor_cron_jobs()
+            .pause_all(agent_id)
+            .await
+    }
+
+    pub async fn delete(id: &CronJobId) -> Result<(), SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .agent_executor_cron_jobs()
+            .delete(id)
+            .await
+    }
+}
+
+impl PageNumbered for AgentExecutorCronJob {}
+
+// Filters of `AgentExecutorCronJob::list_with`
+impl ListQuery<AgentExecutorCronJob> {
+    pub fn agent_id(self, agent_id: &AgentId) -> Self {
+        self.param("agent_id", agent_id.as_str())
+    }
+
+    // Only cron jobs whose name contains `name`
+    pub fn search(self, name: impl Into<String>) -> Self {
+        self.param("search", name)
+    }
+
+    pub fn status(self, status: CronJobStatus) -> Self {
+        self.param("status", status.as_str())
+    }
 }
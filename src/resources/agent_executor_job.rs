@@ -1,18 +1,74 @@
-use serde::{Deserialize, Serialize};
+use futures_util::{stream, Stream, TryStreamExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
-use std::error::Error;
+use std::time::Duration;
 
-use crate::utils::client::SwarmClient as Client;
-use crate::utils::pagination::CursorPaginatedResource;
+use crate::resources::execution::Execution;
+use crate::utils::client::{with_timeout, RequestOptions, SwarmNodeClient};
+use crate::utils::error::SwarmNodeError;
+use crate::utils::id::{AgentId, ExecutorJobId};
+use crate::utils::pagination::{filters_of, CursorPaginatedResource};
+use crate::utils::patch;
+use crate::utils::query::ListQuery;
+use crate::utils::time::{self, Timestamp};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AgentExecutorJob {
-    pub id: String,
-    pub agent_id: String,
+    pub id: ExecutorJobId,
+    pub agent_id: AgentId,
     pub execution_address: String,
-    pub created: String,
+    pub created: Timestamp,
 }
+
+impl AgentExecutorJob {
+    // Time since the job was created, `None` if `created` cannot be read
+    pub fn age(&self) -> Option<Duration> {
+        time::elapsed_since(&self.created)
+    }
+}
+
+// Body of `AgentExecutorJobs::create_with`. The agent is required and
+// checked before anything is sent.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AgentExecutorJobCreate {
+    pub agent_id: Option<AgentId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<Value>,
+    // Sent as the `Idempotency-Key` header, not in the body
+    #[serde(skip)]
+    pub idempotency_key: Option<String>,
+}
+
+impl AgentExecutorJobCreate {
+    pub fn new() -> Self {
+        AgentExecutorJobCreate::default()
+    }
+
+    pub fn agent_id(mut self, agent_id: AgentId) -> Self {
+        self.agent_id = Some(agent_id);
+        self
+    }
+
+    // Value passed to the agent's `main`, serialized to JSON
+    pub fn payload<P: Serialize + ?Sized>(mut self, payload: &P) -> Result<Self, SwarmNodeError> {
+        let payload = serde_json::to_value(payload).map_err(|e| {
+            SwarmNodeError::InvalidArgument(format!("Failed to serialize payload: {}", e))
+        })?;
+        self.payload = Some(payload);
+        Ok(self)
+    }
+
+    // Idempotency key of the create, see `RequestOptions::for_create`
+    pub fn idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.idempotency_key = Some(key.into());
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), SwarmNodeError> {
+        patch::required("agent_id", self.agent_id.as_ref().map(AgentId::as_str))
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AgentExecutorJobList {
     pub next: Option<String>,
@@ -20,68 +76,265 @@ pub struct AgentExecutorJobList {
     pub results: Vec<AgentExecutorJob>,
 }
 
-impl AgentExecutorJob {
-    pub fn api_source() -> &'static str {
-        "agent-executor-jobs"
+// AgentExecutorJob endpoints bound to a specific client
+pub struct AgentExecutorJobs<'a> {
+    client: &'a SwarmNodeClient,
+    options: RequestOptions,
+}
+
+impl<'a> AgentExecutorJobs<'a> {
+    pub fn new(client: &'a SwarmNodeClient) -> Self {
+        AgentExecutorJobs {
+            client,
+            options: RequestOptions::default(),
+        }
+    }
+
+    // Total timeout for calls made through this handle, overriding the
+    // client's default
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
     }
 
     pub async fn list(
-        agent_id: Option<String>,
-    ) -> Result<CursorPaginatedResource<AgentExecutorJob>, Box<dyn Error>> {
-        let mut params = HashMap::new();
+        &self,
+        agent_id: Option<AgentId>,
+    ) -> Result<CursorPaginatedResource<AgentExecutorJob>, SwarmNodeError> {
+        let mut query = ListQuery::<AgentExecutorJob>::new();
         if let Some(agent_id) = agent_id {
-            params.insert("agent_id".to_string(), agent_id);
+            query = query.agent_id(&agent_id);
         }
+        self.list_with(&query).await
+    }
 
-        let response = Client::request_action::<AgentExecutorJobList>(
-            "GET",
-            &format!("{}/", Self::api_source()),
-            Some(params),
-            None,
-        )
-        .await
-        .map_err(|e| format!("Error listing AgentExecutorJobs: {}", e))?;
+    // Executor jobs matching `query`
+    pub async fn list_with(
+        &self,
+        query: &ListQuery<AgentExecutorJob>,
+    ) -> Result<CursorPaginatedResource<AgentExecutorJob>, SwarmNodeError> {
+        let params = query.params();
+        let filters = filters_of(&params);
+        let response = self
+            .client
+            .request_action::<AgentExecutorJobList, _>(
+                "GET",
+                &format!("{}/", AgentExecutorJob::api_source()),
+                Some(params),
+                None::<&Value>,
+                &self.options,
+            )
+            .await?;
 
         Ok(CursorPaginatedResource {
             next_url: response.next,
             previous_url: response.previous,
             results: response.results,
-            resource_class: "AgentExecutorJob",
+            resource_class: "AgentExecutorJob".to_string(),
+            filters,
+            client: self.client.clone(),
         })
     }
 
-    pub async fn retrieve(id: &str) -> Result<AgentExecutorJob, Box<dyn Error>> {
-        let agent_executor_job = Client::request_action::<AgentExecutorJob>(
-            "GET",
-            &format!("{}/{}/", Self::api_source(), id),
-            None,
-            None,
-        )
-        .await
-        .map_err(|e| format!("Error retrieving AgentExecutorJob: {}", e))?;
+    // Every AgentExecutorJob matching the filters, fetching pages lazily as the
+    // stream is polled
+    pub fn list_all(
+        &self,
+        agent_id: Option<AgentId>,
+    ) -> impl Stream<Item = Result<AgentExecutorJob, SwarmNodeError>> + '_ {
+        stream::once(self.list(agent_id))
+            .map_ok(CursorPaginatedResource::into_stream)
+            .try_flatten()
+    }
+
+    pub async fn retrieve(&self, id: &ExecutorJobId) -> Result<AgentExecutorJob, SwarmNodeError> {
+        let agent_executor_job = self
+            .client
+            .request_action::<AgentExecutorJob, _>(
+                "GET",
+                &format!("{}/{}/", AgentExecutorJob::api_source(), id),
+                None,
+                None::<&Value>,
+                &self.options,
+            )
+            .await?;
 
         Ok(agent_executor_job)
     }
 
-    pub async fn create(
-        agent_id: &str,
-        payload: Option<Value>,
-    ) -> Result<AgentExecutorJob, Box<dyn Error>> {
-        let mut data = HashMap::new();
-        data.insert("agent_id".to_string(), agent_id.to_string());
-        if let Some(p) = payload {
-            data.insert("payload".to_string(), serde_json::to_string(&p)?);
-        }
+    // Start an execution of the agent without a payload
+    pub async fn create(&self, agent_id: &AgentId) -> Result<AgentExecutorJob, SwarmNodeError> {
+        self.create_with(&AgentExecutorJobCreate::new().agent_id(agent_id.clone()))
+            .await
+    }
 
-        let agent_executor_job = Client::request_action::<AgentExecutorJob>(
-            "POST",
-            &format!("{}/create/", Self::api_source()),
-            None,
-            Some(data),
-        )
-        .await
-        .map_err(|e| format!("Error creating AgentExecutorJob: {}", e))?;
+    // Start an execution of the agent. `payload` is serialized to JSON and
+    // passed to the agent's `main`.
+    pub async fn create_with_payload<P: Serialize + ?Sized>(
+        &self,
+        agent_id: &AgentId,
+        payload: &P,
+    ) -> Result<AgentExecutorJob, SwarmNodeError> {
+        let job = AgentExecutorJobCreate::new()
+            .agent_id(agent_id.clone())
+            .payload(payload)?;
+        self.create_with(&job).await
+    }
+
+    pub async fn create_with(
+        &self,
+        job: &AgentExecutorJobCreate,
+    ) -> Result<AgentExecutorJob, SwarmNodeError> {
+        job.validate()?;
+
+        let agent_executor_job = self
+            .client
+            .request_action::<AgentExecutorJob, _>(
+                "POST",
+                &format!("{}/create/", AgentExecutorJob::api_source()),
+                None,
+                Some(job),
+                &self.options.for_create(job.idempotency_key.as_deref()),
+            )
+            .await?;
 
         Ok(agent_executor_job)
     }
+
+    // Execution of `job`, once it has finished. Fails with
+    // `SwarmNodeError::Timeout` when `timeout`, or else the client's
+    // `HttpConfig::listen_timeout`, passes first.
+    pub async fn wait(
+        &self,
+        job: &AgentExecutorJob,
+        timeout: Option<Duration>,
+    ) -> Result<Execution, SwarmNodeError> {
+        let what = format!("job {} to finish", job.id);
+        let listen = self.client.listen_to_execution(&job.execution_address);
+        let message = with_timeout(timeout, &what, listen).await?;
+        serde_json::from_str(&message).map_err(|e| {
+            SwarmNodeError::Decode(format!("Invalid execution of job {}: {}", job.id, e))
+        })
+    }
+
+    // Execute the agent with `payload` and decode what it returned as `R`.
+    // Fails with `SwarmNodeError::ExecutionFailed` when the execution does
+    // not succeed, and like `wait` when it takes longer than `timeout`.
+    pub async fn run<P, R>(
+        &self,
+        agent_id: &AgentId,
+        payload: &P,
+        timeout: Option<Duration>,
+    ) -> Result<R, SwarmNodeError>
+    where
+        P: Serialize + ?Sized,
+        R: DeserializeOwned,
+    {
+        let job = self.create_with_payload(agent_id, payload).await?;
+        let execution = self.wait(&job, timeout).await?;
+        if !execution.is_success() {
+            return Err(SwarmNodeError::ExecutionFailed(Box::new(execution)));
+        }
+        execution.return_value_as()
+    }
+}
+
+// Shorthands over the client configured with `set_config`
+impl AgentExecutorJob {
+    pub fn api_source() -> &'static str {
+        "agent-executor-jobs"
+    }
+
+    pub async fn list(
+        agent_id: Option<AgentId>,
+    ) -> Result<CursorPaginatedResource<AgentExecutorJob>, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .agent_executor_jobs()
+            .list(agent_id)
+            .await
+    }
+
+    pub fn list_all(
+        agent_id: Option<AgentId>,
+    ) -> impl Stream<Item = Result<AgentExecutorJob, SwarmNodeError>> {
+        stream::once(async move {
+            SwarmNodeClient::global()?
+                .agent_executor_jobs()
+                .list(agent_id)
+                .await
+        })
+        .map_ok(CursorPaginatedResource::into_stream)
+        .try_flatten()
+    }
+
+    pub async fn list_with(
+        query: &ListQuery<AgentExecutorJob>,
+    ) -> Result<CursorPaginatedResource<AgentExecutorJob>, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .agent_executor_jobs()
+            .list_with(query)
+            .await
+    }
+
+    pub async fn retrieve(id: &ExecutorJobId) -> Result<AgentExecutorJob, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .agent_executor_jobs()
+            .retrieve(id)
+            .await
+    }
+
+    pub async fn create(agent_id: &AgentId) -> Result<AgentExecutorJob, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .agent_executor_jobs()
+            .create(agent_id)
+            .await
+    }
+
+    pub async fn create_with_payload<P: Serialize + ?Sized>(
+        agent_id: &AgentId,
+        payload: &P,
+    ) -> Result<AgentExecutorJob, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .agent_executor_jobs()
+            .create_with_payload(agent_id, payload)
+            .await
+    }
+
+    pub async fn create_with(
+        job: &AgentExecutorJobCreate,
+    ) -> Result<AgentExecutorJob, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .agent_executor_jobs()
+            .create_with(job)
+            .await
+    }
+
+    pub async fn wait(&self, timeout: Option<Duration>) -> Result<Execution, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .agent_executor_jobs()
+            .wait(self, timeout)
+            .await
+    }
+
+    pub async fn run<P, R>(
+        agent_id: &AgentId,
+        payload: &P,
+        timeout: Option<Duration>,
+    ) -> Result<R, SwarmNodeError>
+    where
+        P: Serialize + ?Sized,
+        R: DeserializeOwned,
+    {
+        SwarmNodeClient::global()?
+            .agent_executor_jobs()
+            .run(agent_id, payload, timeout)
+            .await
+    }
+}
+
+// Filters of `AgentExecutorJob::list_with`
+impl ListQuery<AgentExecutorJob> {
+    pub fn agent_id(self, agent_id: &AgentId) -> Self {
+        self.param("agent_id", agent_id.as_str())
+    }
 }
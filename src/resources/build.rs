@@ -1,17 +1,79 @@
+use futures_util::{future, stream, Stream, TryStreamExt};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::error::Error;
+use serde_json::Value;
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, SystemTime};
 
-use crate::utils::client::SwarmClient as Client;
-use crate::utils::pagination::PagePaginatedResource;
+use crate::resources::execution::LogEntry;
+use crate::utils::client::{self, RequestOptions, SwarmNodeClient};
+use crate::utils::error::SwarmNodeError;
+use crate::utils::id::{AgentId, BuildId, ExecutorJobId};
+use crate::utils::pagination::{filters_of, PagePaginatedResource};
+use crate::utils::query::{ListQuery, PageNumbered};
+use crate::utils::time::{self, Timestamp};
+
+// How often the build waits check on a build
+const BUILD_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// How long the build waits run when no timeout is given
+pub const DEFAULT_BUILD_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+// How far the local clock may be ahead of the server's when matching the
+// `created` time of a build against a local `since`
+const CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildStatus {
+    InProgress,
+    Success,
+    Failure,
+    // A status added to the API after this version of the crate
+    #[serde(other)]
+    Unknown,
+}
+
+impl BuildStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BuildStatus::InProgress => "in_progress",
+            BuildStatus::Success => "success",
+            BuildStatus::Failure => "failure",
+            BuildStatus::Unknown => "unknown",
+        }
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, BuildStatus::Success | BuildStatus::Failure)
+    }
+}
+
+impl fmt::Display for BuildStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Build {
-    pub id: String,
-    pub agent_id: String,
+    pub id: BuildId,
+    pub agent_id: AgentId,
     pub build_address: String,
-    pub created: String,
+    pub status: BuildStatus,
+    // Output of installing the requirements and preparing the agent
+    #[serde(default)]
+    pub logs: Vec<LogEntry>,
+    pub created: Timestamp,
 }
+
+impl Build {
+    // Time since the build was created, `None` if `created` cannot be read
+    pub fn age(&self) -> Option<Duration> {
+        time::elapsed_since(&self.created)
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct BuildList {
     pub next: Option<String>,
@@ -21,31 +83,57 @@ pub struct BuildList {
     pub current_page: u32,
 }
 
-impl Build {
-    pub fn api_source() -> &'static str {
-        "builds"
+// Build endpoints bound to a specific client
+pub struct Builds<'a> {
+    client: &'a SwarmNodeClient,
+    options: RequestOptions,
+}
+
+impl<'a> Builds<'a> {
+    pub fn new(client: &'a SwarmNodeClient) -> Self {
+        Builds {
+            client,
+            options: RequestOptions::default(),
+        }
+    }
+
+    // Total timeout for calls made through this handle, overriding the
+    // client's default
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
     }
 
     pub async fn list(
-        agent_executor_job_id: Option<String>,
+        &self,
+        agent_executor_job_id: Option<ExecutorJobId>,
         page: Option<u32>,
-        page_size: Option<u8>,
-    ) -> Result<PagePaginatedResource<Build>, Box<dyn Error>> {
-        let mut params = HashMap::new();
-        params.insert("page".to_string(), page.unwrap_or(1).to_string());
-        params.insert("page_size".to_string(), page_size.unwrap_or(10).to_string());
+        page_size: Option<u32>,
+    ) -> Result<PagePaginatedResource<Build>, SwarmNodeError> {
+        let mut query = ListQuery::<Build>::paged(page, page_size);
         if let Some(agent_executor_job_id) = agent_executor_job_id {
-            params.insert("agent_executor_job_id".to_string(), agent_executor_job_id);
+            query = query.agent_executor_job_id(&agent_executor_job_id);
         }
+        self.list_with(&query).await
+    }
 
-        let response = Client::request_action::<BuildList>(
-            "GET",
-            &format!("{}/", Self::api_source()),
-            Some(params),
-            None,
-        )
-        .await
-        .map_err(|e| format!("Error listing Builds: {}", e))?;
+    // Builds matching `query`
+    pub async fn list_with(
+        &self,
+        query: &ListQuery<Build>,
+    ) -> Result<PagePaginatedResource<Build>, SwarmNodeError> {
+        let params = query.page_params(self.client.max_page_size())?;
+        let filters = filters_of(&params);
+        let response = self
+            .client
+            .request_action::<BuildList, _>(
+                "GET",
+                &format!("{}/", Build::api_source()),
+                Some(params),
+                None::<&Value>,
+                &self.options,
+            )
+            .await?;
 
         Ok(PagePaginatedResource {
             next_url: response.next,
@@ -53,20 +141,278 @@ impl Build {
             results: response.results,
             total_count: response.total_count,
             current_page: response.current_page,
-            resource_class: "Build",
+            resource_class: "Build".to_string(),
+            filters,
+            client: self.client.clone(),
         })
     }
 
-    pub async fn retrieve(id: &str) -> Result<Build, Box<dyn Error>> {
-        let build = Client::request_action::<Build>(
-            "GET",
-            &format!("{}/{}/", Self::api_source(), id),
-            None,
-            None,
-        )
-        .await
-        .map_err(|e| format!("Error retrieving Build: {}", e))?;
+    // Every Build matching the filters, fetching pages lazily as the
+    // stream is polled
+    pub fn list_all(
+        &self,
+        agent_executor_job_id: Option<ExecutorJobId>,
+        page_size: Option<u32>,
+    ) -> impl Stream<Item = Result<Build, SwarmNodeError>> + '_ {
+        stream::once(self.list(agent_executor_job_id, Some(1), page_size))
+            .map_ok(PagePaginatedResource::into_stream)
+            .try_flatten()
+    }
+
+    pub async fn retrieve(&self, id: &BuildId) -> Result<Build, SwarmNodeError> {
+        let build = self
+            .client
+            .request_action::<Build, _>(
+                "GET",
+                &format!("{}/{}/", Build::api_source(), id),
+                None,
+                None::<&Value>,
+                &self.options,
+            )
+            .await?;
 
         Ok(build)
     }
+
+    // First build of the agent created at or after `since`, `None` when
+    // there is none yet
+    pub async fn first_build_since(
+        &self,
+        agent_id: &AgentId,
+        since: impl Into<SystemTime>,
+    ) -> Result<Option<Build>, SwarmNodeError> {
+        let since = since.into();
+        let query = ListQuery::<Build>::new()
+            .agent_id(agent_id)
+            .created_after(since)
+            .order_by("created");
+        // Checked here too in case the API ignores a filter
+        let builds: Vec<_> = self
+            .list_with(&query)
+            .await?
+            .into_stream()
+            .try_filter(|build| {
+                let created = time::to_system_time(&build.created);
+                future::ready(build.agent_id == *agent_id && created >= Some(since))
+            })
+            .try_collect()
+            .await?;
+        Ok(builds
+            .into_iter()
+            .min_by_key(|build| time::to_system_time(&build.created)))
+    }
+
+    // Most recent build of the agent, `None` when it has none
+    pub async fn latest_build(&self, agent_id: &AgentId) -> Result<Option<Build>, SwarmNodeError> {
+        let query = ListQuery::<Build>::new()
+            .agent_id(agent_id)
+            .order_by_desc("created");
+        let page = self.list_with(&query).await?;
+        Ok(page
+            .results
+            .into_iter()
+            .filter(|build| build.agent_id == *agent_id)
+            .max_by_key(|build| time::to_system_time(&build.created)))
+    }
+
+    // Wait for the latest build of the agent to succeed or fail, e.g. the one
+    // `Agent::create` started. Fails with `SwarmNodeError::BuildFailed`,
+    // which carries the build logs, when the build fails, and with
+    // `SwarmNodeError::Timeout` when `timeout` passes first.
+    // `DEFAULT_BUILD_TIMEOUT` applies when `timeout` is `None`.
+    pub async fn wait_for_build(
+        &self,
+        agent_id: &AgentId,
+        timeout: Option<Duration>,
+    ) -> Result<Build, SwarmNodeError> {
+        let what = format!("the latest build of agent {}", agent_id);
+        with_timeout(timeout, what, async {
+            // The first build may take a moment to be queued
+            let build = loop {
+                match self.latest_build(agent_id).await? {
+                    Some(build) => break build,
+                    None => tokio::time::sleep(BUILD_POLL_INTERVAL).await,
+                }
+            };
+            self.until_finished(build).await
+        })
+        .await
+    }
+
+    // Poll build `id` until it succeeds or fails. Fails like
+    // `wait_for_build`.
+    pub async fn wait_for_build_id(
+        &self,
+        id: &BuildId,
+        timeout: Option<Duration>,
+    ) -> Result<Build, SwarmNodeError> {
+        with_timeout(timeout, format!("build {}", id), async {
+            let build = self.retrieve(id).await?;
+            self.until_finished(build).await
+        })
+        .await
+    }
+
+    // Wait for the build that `Agent::update` started: the first build of
+    // the agent created at or after `since`, which should be taken just
+    // before that call. Unlike `wait_for_build`, this cannot pick up a build
+    // that was already there. Builds created up to a minute before `since`
+    // also match, in case the local clock is ahead of the server's. Fails
+    // like `wait_for_build`.
+    pub async fn wait_for_new_build(
+        &self,
+        agent_id: &AgentId,
+        since: impl Into<SystemTime>,
+        timeout: Option<Duration>,
+    ) -> Result<Build, SwarmNodeError> {
+        let since = since.into();
+        let since = since.checked_sub(CLOCK_SKEW).unwrap_or(since);
+        let what = format!("a new build of agent {}", agent_id);
+        with_timeout(timeout, what, async {
+            // The build may take a moment to be queued
+            let build = loop {
+                match self.first_build_since(agent_id, since).await? {
+                    Some(build) => break build,
+                    None => tokio::time::sleep(BUILD_POLL_INTERVAL).await,
+                }
+            };
+            self.until_finished(build).await
+        })
+        .await
+    }
+
+    async fn until_finished(&self, mut build: Build) -> Result<Build, SwarmNodeError> {
+        loop {
+            match build.status {
+                BuildStatus::Success => return Ok(build),
+                BuildStatus::Failure => return Err(SwarmNodeError::BuildFailed(Box::new(build))),
+                // Waiting on a status this crate cannot read could never end
+                BuildStatus::Unknown => {
+                    return Err(SwarmNodeError::Decode(format!(
+                        "Build {} has a status this version of the crate does not know",
+                        build.id
+                    )))
+                }
+                BuildStatus::InProgress => {}
+            }
+            tokio::time::sleep(BUILD_POLL_INTERVAL).await;
+            build = self.retrieve(&build.id).await?;
+        }
+    }
+}
+
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    what: String,
+    future: impl Future<Output = Result<T, SwarmNodeError>>,
+) -> Result<T, SwarmNodeError> {
+    let timeout = timeout.unwrap_or(DEFAULT_BUILD_TIMEOUT);
+    client::with_timeout(Some(timeout), &what, future).await
+}
+
+// Shorthands over the client configured with `set_config`
+impl Build {
+    pub fn api_source() -> &'static str {
+        "builds"
+    }
+
+    pub async fn list(
+        agent_executor_job_id: Option<ExecutorJobId>,
+        page: Option<u32>,
+        page_size: Option<u32>,
+    ) -> Result<PagePaginatedResource<Build>, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .builds()
+            .list(agent_executor_job_id, page, page_size)
+            .await
+    }
+
+    pub fn list_all(
+        agent_executor_job_id: Option<ExecutorJobId>,
+        page_size: Option<u32>,
+    ) -> impl Stream<Item = Result<Build, SwarmNodeError>> {
+        stream::once(async move {
+            SwarmNodeClient::global()?
+                .builds()
+                .list(agent_executor_job_id, Some(1), page_size)
+                .await
+        })
+        .map_ok(PagePaginatedResource::into_stream)
+        .try_flatten()
+    }
+
+    pub async fn list_with(
+        query: &ListQuery<Build>,
+    ) -> Result<PagePaginatedResource<Build>, SwarmNodeError> {
+        SwarmNodeClient::global()?.builds().list_with(query).await
+    }
+
+    pub async fn retrieve(id: &BuildId) -> Result<Build, SwarmNodeError> {
+        SwarmNodeClient::global()?.builds().retrieve(id).await
+    }
+
+    pub async fn first_build_since(
+        agent_id: &AgentId,
+        since: impl Into<SystemTime>,
+    ) -> Result<Option<Build>, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .builds()
+            .first_build_since(agent_id, since)
+            .await
+    }
+
+    pub async fn latest_build(agent_id: &AgentId) -> Result<Option<Build>, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .builds()
+            .latest_build(agent_id)
+            .await
+    }
+
+    pub async fn wait_for_build(
+        agent_id: &AgentId,
+        timeout: Option<Duration>,
+    ) -> Result<Build, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .builds()
+            .wait_for_build(agent_id, timeout)
+            .await
+    }
+
+    pub async fn wait_for_build_id(
+        id: &BuildId,
+        timeout: Option<Duration>,
+    ) -> Result<Build, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .builds()
+            .wait_for_build_id(id, timeout)
+            .await
+    }
+
+    pub async fn wait_for_new_build(
+        agent_id: &AgentId,
+        since: impl Into<SystemTime>,
+        timeout: Option<Duration>,
+    ) -> Result<Build, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .builds()
+            .wait_for_new_build(agent_id, since, timeout)
+            .await
+    }
+}
+
+impl PageNumbered for Build {}
+
+// Filters of `Build::list_with`
+impl ListQuery<Build> {
+    pub fn agent_id(self, agent_id: &AgentId) -> Self {
+        self.param("agent_id", agent_id.as_str())
+    }
+
+    pub fn agent_executor_job_id(self, agent_executor_job_id: &ExecutorJobId) -> Self {
+        self.param("agent_executor_job_id", agent_executor_job_id.as_str())
+    }
+
+    pub fn status(self, status: BuildStatus) -> Self {
+        self.param("status", status.as_str())
+    }
 }
@@ -1,17 +1,129 @@
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::error::Error;
+use futures_util::{stream, Stream, TryStreamExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::time::Duration;
 
-use crate::utils::client::SwarmClient as Client;
-use crate::utils::pagination::CursorPaginatedResource;
+use crate::utils::client::{RequestOptions, SwarmNodeClient};
+use crate::utils::error::SwarmNodeError;
+use crate::utils::id::{AgentId, CronJobId, ExecutionId, ExecutorJobId};
+use crate::utils::pagination::{filters_of, CursorPaginatedResource};
+use crate::utils::query::ListQuery;
+use crate::utils::time::{self, Timestamp};
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionStatus {
+    Success,
+    InProgress,
+    Failure,
+    Termination,
+    // A status added to the API after this version of the crate
+    #[serde(other)]
+    Unknown,
+}
+
+impl ExecutionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExecutionStatus::Success => "success",
+            ExecutionStatus::InProgress => "in_progress",
+            ExecutionStatus::Failure => "failure",
+            ExecutionStatus::Termination => "termination",
+            ExecutionStatus::Unknown => "unknown",
+        }
+    }
+
+    // Whether the execution has stopped and its status will not change
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            ExecutionStatus::Success | ExecutionStatus::Failure | ExecutionStatus::Termination
+        )
+    }
+}
+
+impl fmt::Display for ExecutionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+    #[serde(other)]
+    Unknown,
+}
+
+// One line of output written by the agent script
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LogEntry {
+    pub content: String,
+    #[serde(rename = "type")]
+    pub stream: LogStream,
+    #[serde(default)]
+    pub timestamp: Option<Timestamp>,
+}
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Execution {
-    pub id: String,
-    pub agent_id: String,
+    pub id: ExecutionId,
+    pub agent_id: AgentId,
     pub execution_address: String,
-    pub created: String,
+    #[serde(default)]
+    pub agent_executor_job_id: Option<ExecutorJobId>,
+    #[serde(default)]
+    pub agent_executor_cron_job_id: Option<CronJobId>,
+    pub status: ExecutionStatus,
+    // Unset while pending and running respectively
+    #[serde(default)]
+    pub start: Option<Timestamp>,
+    #[serde(default)]
+    pub finish: Option<Timestamp>,
+    #[serde(default)]
+    pub logs: Vec<LogEntry>,
+    // Whatever the agent's `main` returned, `null` until it finishes
+    #[serde(default)]
+    pub return_value: Value,
+    pub created: Timestamp,
 }
+
+impl Execution {
+    pub fn is_terminal(&self) -> bool {
+        self.status.is_terminal()
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status == ExecutionStatus::Success
+    }
+
+    // Decode the return value as `R`
+    pub fn return_value_as<R: DeserializeOwned>(&self) -> Result<R, SwarmNodeError> {
+        R::deserialize(&self.return_value).map_err(|e| {
+            SwarmNodeError::Decode(format!(
+                "Return value of execution {} is not a valid {}: {}",
+                self.id,
+                std::any::type_name::<R>(),
+                e
+            ))
+        })
+    }
+
+    // Time between start and finish, `None` until the execution finishes
+    pub fn duration(&self) -> Option<Duration> {
+        time::between(self.start.as_ref()?, self.finish.as_ref()?)
+    }
+
+    // Time since the execution was created, `None` if `created` cannot be
+    // read
+    pub fn age(&self) -> Option<Duration> {
+        time::elapsed_since(&self.created)
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ExecutionList {
     pub next: Option<String>,
@@ -19,57 +131,272 @@ pub struct ExecutionList {
     pub results: Vec<Execution>,
 }
 
-impl Execution {
-    pub fn api_source() -> &'static str {
-        "executions"
+// Execution endpoints bound to a specific client
+pub struct Executions<'a> {
+    client: &'a SwarmNodeClient,
+    options: RequestOptions,
+}
+
+impl<'a> Executions<'a> {
+    pub fn new(client: &'a SwarmNodeClient) -> Self {
+        Executions {
+            client,
+            options: RequestOptions::default(),
+        }
+    }
+
+    // Total timeout for calls made through this handle, overriding the
+    // client's default
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
     }
 
     pub async fn list(
-        agent_id: Option<String>,
-        agent_executor_job_id: Option<String>,
-        agent_executor_cron_job_id: Option<String>,
-    ) -> Result<CursorPaginatedResource<Execution>, Box<dyn Error>> {
-        let mut params = HashMap::new();
+        &self,
+        agent_id: Option<AgentId>,
+        agent_executor_job_id: Option<ExecutorJobId>,
+        agent_executor_cron_job_id: Option<CronJobId>,
+    ) -> Result<CursorPaginatedResource<Execution>, SwarmNodeError> {
+        let mut query = ListQuery::<Execution>::new();
         if let Some(agent_id) = agent_id {
-            params.insert("agent_id".to_string(), agent_id);
+            query = query.agent_id(&agent_id);
         }
         if let Some(agent_executor_job_id) = agent_executor_job_id {
-            params.insert("agent_executor_job_id".to_string(), agent_executor_job_id);
+            query = query.agent_executor_job_id(&agent_executor_job_id);
         }
         if let Some(agent_executor_cron_job_id) = agent_executor_cron_job_id {
-            params.insert(
-                "agent_executor_cron_job_id".to_string(),
-                agent_executor_cron_job_id,
-            );
+            query = query.agent_executor_cron_job_id(&agent_executor_cron_job_id);
         }
+        self.list_with(&query).await
+    }
 
-        let response = Client::request_action::<ExecutionList>(
-            "GET",
-            &format!("{}/", Self::api_source()),
-            Some(params),
-            None,
-        )
-        .await
-        .map_err(|e| format!("Error listing Executions: {}", e))?;
+    // Executions matching `query`
+    pub async fn list_with(
+        &self,
+        query: &ListQuery<Execution>,
+    ) -> Result<CursorPaginatedResource<Execution>, SwarmNodeError> {
+        let params = query.params();
+        let filters = filters_of(&params);
+        let response = self
+            .client
+            .request_action::<ExecutionList, _>(
+                "GET",
+                &format!("{}/", Execution::api_source()),
+                Some(params),
+                None::<&Value>,
+                &self.options,
+            )
+            .await?;
 
         Ok(CursorPaginatedResource {
             next_url: response.next,
             previous_url: response.previous,
             results: response.results,
-            resource_class: "Execution",
+            resource_class: "Execution".to_string(),
+            filters,
+            client: self.client.clone(),
         })
     }
 
-    pub async fn retrieve(id: &str) -> Result<Execution, Box<dyn Error>> {
-        let execution = Client::request_action::<Execution>(
-            "GET",
-            &format!("{}/{}/", Self::api_source(), id),
-            None,
-            None,
-        )
-        .await
-        .map_err(|e| format!("Error retrieving Execution: {}", e))?;
+    // Every Execution matching the filters, fetching pages lazily as the
+    // stream is polled
+    pub fn list_all(
+        &self,
+        agent_id: Option<AgentId>,
+        agent_executor_job_id: Option<ExecutorJobId>,
+        agent_executor_cron_job_id: Option<CronJobId>,
+    ) -> impl Stream<Item = Result<Execution, SwarmNodeError>> + '_ {
+        stream::once(self.list(agent_id, agent_executor_job_id, agent_executor_cron_job_id))
+            .map_ok(CursorPaginatedResource::into_stream)
+            .try_flatten()
+    }
+
+    pub async fn retrieve(&self, id: &ExecutionId) -> Result<Execution, SwarmNodeError> {
+        let execution = self
+            .client
+            .request_action::<Execution, _>(
+                "GET",
+                &format!("{}/{}/", Execution::api_source(), id),
+                None,
+                None::<&Value>,
+                &self.options,
+            )
+            .await?;
 
         Ok(execution)
     }
 }
+
+// Shorthands over the client configured with `set_config`
+impl Execution {
+    pub fn api_source() -> &'static str {
+        "executions"
+    }
+
+    pub async fn list(
+        agent_id: Option<AgentId>,
+        agent_executor_job_id: Option<ExecutorJobId>,
+        agent_executor_cron_job_id: Option<CronJobId>,
+    ) -> Result<CursorPaginatedResource<Execution>, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .executions()
+            .list(agent_id, agent_executor_job_id, agent_executor_cron_job_id)
+            .await
+    }
+
+    pub fn list_all(
+        agent_id: Option<AgentId>,
+        agent_executor_job_id: Option<ExecutorJobId>,
+        agent_executor_cron_job_id: Option<CronJobId>,
+    ) -> impl Stream<Item = Result<Execution, SwarmNodeError>> {
+        stream::once(async move {
+            SwarmNodeClient::global()?
+                .executions()
+                .list(agent_id, agent_executor_job_id, agent_executor_cron_job_id)
+                .await
+        })
+        .map_ok(CursorPaginatedResource::into_stream)
+        .try_flatten()
+    }
+
+    pub async fn list_with(
+        query: &ListQuery<Execution>,
+    ) -> Result<CursorPaginatedResource<Execution>, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .executions()
+            .list_with(query)
+            .await
+    }
+
+    pub async fn retrieve(id: &ExecutionId) -> Result<Execution, SwarmNodeError> {
+        SwarmNodeClient::global()?.executions().retrieve(id).await
+    }
+}
+
+// Filters of `Execution::list_with`
+impl ListQuery<Execution> {
+    pub fn agent_id(self, agent_id: &AgentId) -> Self {
+        self.param("agent_id", agent_id.as_str())
+    }
+
+    pub fn agent_executor_job_id(self, agent_executor_job_id: &ExecutorJobId) -> Self {
+        self.param("agent_executor_job_id", agent_executor_job_id.as_str())
+    }
+
+    pub fn agent_executor_cron_job_id(self, agent_executor_cron_job_id: &CronJobId) -> Self {
+        self.param(
+            "agent_executor_cron_job_id",
+            agent_executor_cron_job_id.as_str(),
+        )
+    }
+
+    pub fn status(self, status: ExecutionStatus) -> Self {
+        self.param("status", status.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn execution(status: &str, return_value: Value) -> Execution {
+        serde_json::from_value(json!({
+            "id": "e1",
+            "agent_id": "a1",
+            "execution_address": "x",
+            "status": status,
+            "start": "2024-05-01T12:00:00Z",
+            "finish": null,
+            "return_value": return_value,
+            "created": "2024-05-01T12:00:00Z",
+        }))
+        .unwrap()
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Output {
+        total: u32,
+    }
+
+    #[test]
+    fn return_value_is_decoded() {
+        let execution = execution("success", json!({ "total": 3 }));
+        assert_eq!(
+            execution.return_value_as::<Output>().unwrap(),
+            Output { total: 3 }
+        );
+        assert_eq!(
+            execution.return_value_as::<Value>().unwrap(),
+            json!({ "total": 3 })
+        );
+    }
+
+    #[test]
+    fn return_value_of_the_wrong_shape_is_a_decode_error() {
+        for return_value in [json!({ "total": "3" }), json!([1, 2]), Value::Null] {
+            let error = execution("success", return_value)
+                .return_value_as::<Output>()
+                .unwrap_err();
+            assert!(matches!(error, SwarmNodeError::Decode(_)), "{:?}", error);
+            assert!(error.to_string().contains("execution e1"), "{}", error);
+        }
+
+        // `null` is a valid `Option`
+        let execution = execution("in_progress", Value::Null);
+        assert_eq!(execution.return_value_as::<Option<Output>>().unwrap(), None);
+    }
+
+    #[test]
+    fn status_decodes_from_snake_case() {
+        let status = |name: &str| serde_json::from_value::<ExecutionStatus>(json!(name)).unwrap();
+        assert_eq!(status("success"), ExecutionStatus::Success);
+        assert_eq!(status("in_progress"), ExecutionStatus::InProgress);
+        assert_eq!(status("failure"), ExecutionStatus::Failure);
+        assert_eq!(status("termination"), ExecutionStatus::Termination);
+        assert_eq!(status("queued"), ExecutionStatus::Unknown);
+
+        for status in [ExecutionStatus::InProgress, ExecutionStatus::Termination] {
+            assert_eq!(json!(status), json!(status.as_str()));
+        }
+    }
+
+    #[test]
+    fn terminal_and_successful_statuses() {
+        let terminal: Vec<_> = [
+            ExecutionStatus::Success,
+            ExecutionStatus::InProgress,
+            ExecutionStatus::Failure,
+            ExecutionStatus::Termination,
+            ExecutionStatus::Unknown,
+        ]
+        .into_iter()
+        .filter(ExecutionStatus::is_terminal)
+        .collect();
+        assert_eq!(
+            terminal,
+            [
+                ExecutionStatus::Success,
+                ExecutionStatus::Failure,
+                ExecutionStatus::Termination
+            ]
+        );
+
+        assert!(execution("success", Value::Null).is_success());
+        assert!(!execution("termination", Value::Null).is_success());
+        assert!(!execution("in_progress", Value::Null).is_terminal());
+    }
+
+    #[test]
+    fn duration_needs_start_and_finish() {
+        let mut execution = execution("in_progress", Value::Null);
+        assert_eq!(execution.duration(), None);
+
+        execution.finish = serde_json::from_value(json!("2024-05-01T12:00:02.5Z")).unwrap();
+        assert_eq!(execution.duration(), Some(Duration::from_millis(2500)));
+
+        execution.start = None;
+        assert_eq!(execution.duration(), None);
+    }
+}
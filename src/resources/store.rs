@@ -1,18 +1,91 @@
+use futures_util::{stream, Stream, TryStreamExt};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::collections::HashMap;
-use std::error::Error;
+use serde_json::{Map, Value};
+use std::time::Duration;
 
-use crate::utils::client::SwarmClient as Client;
-use crate::utils::pagination::PagePaginatedResource;
+use crate::utils::client::{RequestOptions, SwarmNodeClient};
+use crate::utils::error::SwarmNodeError;
+use crate::utils::id::{AgentId, StoreId};
+use crate::utils::pagination::{filters_of, PagePaginatedResource};
+use crate::utils::patch::{self, Patch};
+use crate::utils::query::{ListQuery, PageNumbered};
+use crate::utils::time::{self, Timestamp};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Store {
-    pub id: String,
-    pub agent_id: String,
+    pub id: StoreId,
+    pub agent_id: AgentId,
     pub store_address: String,
-    pub created: String,
+    pub created: Timestamp,
 }
+
+impl Store {
+    // Time since the store was created, `None` if `created` cannot be read
+    pub fn age(&self) -> Option<Duration> {
+        time::elapsed_since(&self.created)
+    }
+}
+
+// Body of `Stores::create`, the name is required
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StoreCreate {
+    pub name: Option<String>,
+    // Sent as the `Idempotency-Key` header, not in the body
+    #[serde(skip)]
+    pub idempotency_key: Option<String>,
+}
+
+impl StoreCreate {
+    pub fn new() -> Self {
+        StoreCreate::default()
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    // Idempotency key of the create, see `RequestOptions::for_create`
+    pub fn idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.idempotency_key = Some(key.into());
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), SwarmNodeError> {
+        patch::required("name", self.name.as_deref())
+    }
+}
+
+// Body of `Stores::update`. Fields left `Patch::Unchanged` are not sent.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StoreUpdate {
+    #[serde(skip_serializing_if = "Patch::is_unchanged")]
+    pub name: Patch<String>,
+    // Fields this struct does not know about, sent as is
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl StoreUpdate {
+    pub fn new() -> Self {
+        StoreUpdate::default()
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Patch::Set(name.into());
+        self
+    }
+
+    pub fn field(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.extra.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), SwarmNodeError> {
+        patch::required_patch("name", &self.name)
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct StoreList {
     pub next: Option<String>,
@@ -22,31 +95,57 @@ pub struct StoreList {
     pub current_page: u32,
 }
 
-impl Store {
-    pub fn api_source() -> &'static str {
-        "stores"
+// Store endpoints bound to a specific client
+pub struct Stores<'a> {
+    client: &'a SwarmNodeClient,
+    options: RequestOptions,
+}
+
+impl<'a> Stores<'a> {
+    pub fn new(client: &'a SwarmNodeClient) -> Self {
+        Stores {
+            client,
+            options: RequestOptions::default(),
+        }
+    }
+
+    // Total timeout for calls made through this handle, overriding the
+    // client's default
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
     }
 
     pub async fn list(
-        agent_id: Option<String>,
+        &self,
+        agent_id: Option<AgentId>,
         page: Option<u32>,
-        page_size: Option<u8>,
-    ) -> Result<PagePaginatedResource<Store>, Box<dyn Error>> {
-        let mut params = HashMap::new();
-        params.insert("page".to_string(), page.unwrap_or(1).to_string());
-        params.insert("page_size".to_string(), page_size.unwrap_or(10).to_string());
+        page_size: Option<u32>,
+    ) -> Result<PagePaginatedResource<Store>, SwarmNodeError> {
+        let mut query = ListQuery::<Store>::paged(page, page_size);
         if let Some(agent_id) = agent_id {
-            params.insert("agent_id".to_string(), agent_id);
+            query = query.agent_id(&agent_id);
         }
+        self.list_with(&query).await
+    }
 
-        let response = Client::request_action::<StoreList>(
-            "GET",
-            &format!("{}/", Self::api_source()),
-            Some(params),
-            None,
-        )
-        .await
-        .map_err(|e| format!("Error listing stores: {}", e))?;
+    // Stores matching `query`
+    pub async fn list_with(
+        &self,
+        query: &ListQuery<Store>,
+    ) -> Result<PagePaginatedResource<Store>, SwarmNodeError> {
+        let params = query.page_params(self.client.max_page_size())?;
+        let filters = filters_of(&params);
+        let response = self
+            .client
+            .request_action::<StoreList, _>(
+                "GET",
+                &format!("{}/", Store::api_source()),
+                Some(params),
+                None::<&Value>,
+                &self.options,
+            )
+            .await?;
 
         Ok(PagePaginatedResource {
             next_url: response.next,
@@ -54,73 +153,156 @@ impl Store {
             results: response.results,
             total_count: response.total_count,
             current_page: response.current_page,
-            resource_class: "Store",
+            resource_class: "Store".to_string(),
+            filters,
+            client: self.client.clone(),
         })
     }
 
-    pub async fn retrieve(id: &str) -> Result<Store, Box<dyn Error>> {
-        let store = Client::request_action::<Store>(
-            "GET",
-            &format!("{}/{}/", Self::api_source(), id),
-            None,
-            None,
-        )
-        .await
-        .map_err(|e| format!("Error retrieving Store: {}", e))?;
+    // Every Store matching the filters, fetching pages lazily as the
+    // stream is polled
+    pub fn list_all(
+        &self,
+        agent_id: Option<AgentId>,
+        page_size: Option<u32>,
+    ) -> impl Stream<Item = Result<Store, SwarmNodeError>> + '_ {
+        stream::once(self.list(agent_id, Some(1), page_size))
+            .map_ok(PagePaginatedResource::into_stream)
+            .try_flatten()
+    }
+
+    pub async fn retrieve(&self, id: &StoreId) -> Result<Store, SwarmNodeError> {
+        let store = self
+            .client
+            .request_action::<Store, _>(
+                "GET",
+                &format!("{}/{}/", Store::api_source(), id),
+                None,
+                None::<&Value>,
+                &self.options,
+            )
+            .await?;
 
         Ok(store)
     }
 
-    pub async fn create(name: &str) -> Result<Store, Box<dyn Error>> {
-        let mut data = HashMap::new();
-        data.insert("name".to_string(), name.to_string());
+    pub async fn create(&self, store: &StoreCreate) -> Result<Store, SwarmNodeError> {
+        store.validate()?;
 
-        let agent_executor_cron_job = Client::request_action::<Store>(
-            "POST",
-            &format!("{}/create/", Self::api_source()),
-            None,
-            Some(data),
-        )
-        .await
-        .map_err(|e| format!("Error creating Store: {}", e))?;
+        let store = self
+            .client
+            .request_action::<Store, _>(
+                "POST",
+                &format!("{}/create/", Store::api_source()),
+                None,
+                Some(store),
+                &self.options.for_create(store.idempotency_key.as_deref()),
+            )
+            .await?;
 
-        Ok(agent_executor_cron_job)
+        Ok(store)
     }
 
     pub async fn update(
-      id: &str,
-      payload: Option<HashMap<String, Value>>,
-  ) -> Result<Store, Box<dyn Error>> {
-      let mut data = HashMap::new();
-      if let Some(payload) = payload {
-          for (key, value) in payload {
-              data.insert(key, serde_json::to_string(&value).unwrap());
-          }
-      }
-
-      let agent_executor_cron_job = Client::request_action::<Store>(
-          "PATCH",
-          &format!("{}/{}/update/", Self::api_source(), id),
-          None,
-          Some(data),
-      )
-      .await
-      .map_err(|e| format!("Error updating Store: {}", e))?;
-
-      Ok(agent_executor_cron_job)
-  }
-
-  pub async fn delete(id: &str) -> Result<(), Box<dyn Error>> {
-      Client::request_action::<()>(
-          "DELETE",
-          &format!("{}/{}/delete/", Self::api_source(), id),
-          None,
-          None,
-      )
-      .await
-      .map_err(|e| format!("Error deleting Store: {}", e))?;
-
-      Ok(())
-  }
+        &self,
+        id: &StoreId,
+        update: &StoreUpdate,
+    ) -> Result<Store, SwarmNodeError> {
+        update.validate()?;
+
+        let store = self
+            .client
+            .request_action::<Store, _>(
+                "PATCH",
+                &format!("{}/{}/update/", Store::api_source(), id),
+                None,
+                Some(update),
+                &self.options,
+            )
+            .await?;
+
+        Ok(store)
+    }
+
+    pub async fn delete(&self, id: &StoreId) -> Result<(), SwarmNodeError> {
+        self.client
+            .request_action::<(), _>(
+                "DELETE",
+                &format!("{}/{}/delete/", Store::api_source(), id),
+                None,
+                None::<&Value>,
+                &self.options,
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+// Shorthands over the client configured with `set_config`
+impl Store {
+    pub fn api_source() -> &'static str {
+        "stores"
+    }
+
+    pub async fn list(
+        agent_id: Option<AgentId>,
+        page: Option<u32>,
+        page_size: Option<u32>,
+    ) -> Result<PagePaginatedResource<Store>, SwarmNodeError> {
+        SwarmNodeClient::global()?
+            .stores()
+            .list(agent_id, page, page_size)
+            .await
+    }
 
+    pub fn list_all(
+        agent_id: Option<AgentId>,
+        page_size: Option<u32>,
+    ) -> impl Stream<Item = Result<Store, SwarmNodeError>> {
+        stream::once(async move {
+            SwarmNodeClient::global()?
+                .stores()
+                .list(agent_id, Some(1), page_size)
+                .await
+        })
+        .map_ok(PagePaginatedResource::into_stream)
+        .try_flatten()
+    }
+
+    pub async fn list_with(
+        query: &ListQuery<Store>,
+    ) -> Result<PagePaginatedResource<Store>, SwarmNodeError> {
+        SwarmNodeClient::global()?.stores().list_with(query).await
+    }
+
+    pub async fn retrieve(id: &StoreId) -> Result<Store, SwarmNodeError> {
+        SwarmNodeClient::global()?.stores().retrieve(id).await
+    }
+
+    pub async fn create(store: &StoreCreate) -> Result<Store, SwarmNodeError> {
+        SwarmNodeClient::global()?.stores().create(store).await
+    }
+
+    pub async fn update(id: &StoreId, update: &StoreUpdate) -> Result<Store, SwarmNodeError> {
+        SwarmNodeClient::global()?.stores().update(id, update).await
+    }
+
+    pub async fn delete(id: &StoreId) -> Result<(), SwarmNodeError> {
+        SwarmNodeClient::global()?.stores().delete(id).await
+    }
+}
+
+impl PageNumbered for Store {}
+
+// Filters of `Store::list_with`
+impl ListQuery<Store> {
+    pub fn agent_id(self, agent_id: &AgentId) -> Self {
+        self.param("agent_id", agent_id.as_str())
+    }
+
+    // Only stores whose name contains `name`
+    pub fn search(self, name: impl Into<String>) -> Self {
+        self.param("search", name)
+    }
 }
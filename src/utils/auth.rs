@@ -0,0 +1,315 @@
+use futures_util::future::{self, BoxFuture, FutureExt};
+use std::env;
+use std::fmt;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use tokio::process::Command;
+
+use super::error::SwarmNodeError;
+
+pub const API_KEY_ENV_VAR: &str = "SWARMNODE_API_KEY";
+
+// Source of the API key sent with every request. `refresh` is called when
+// the API answers 401 so that providers backed by a secrets manager can pick
+// up a rotated key; it returns whether the key changed, and the request is
+// sent once more only if it did.
+//
+// Both methods run on the request path inside the async runtime, so they
+// must not block: the file and command providers load the key with tokio's
+// async file and process APIs and cache it until the next refresh.
+pub trait AuthProvider: fmt::Debug + Send + Sync {
+    fn api_key(&self) -> BoxFuture<'_, Result<String, SwarmNodeError>>;
+
+    fn refresh(&self) -> BoxFuture<'_, Result<bool, SwarmNodeError>> {
+        future::ready(Ok(false)).boxed()
+    }
+}
+
+// A fixed API key
+#[derive(Clone)]
+pub struct StaticKey {
+    key: String,
+}
+
+impl StaticKey {
+    pub fn new(key: impl Into<String>) -> Self {
+        StaticKey { key: key.into() }
+    }
+}
+
+impl fmt::Debug for StaticKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StaticKey")
+            .field("key", &"<redacted>")
+            .finish()
+    }
+}
+
+impl AuthProvider for StaticKey {
+    fn api_key(&self) -> BoxFuture<'_, Result<String, SwarmNodeError>> {
+        future::ready(non_empty(self.key.clone())).boxed()
+    }
+}
+
+// API key read from an environment variable on every request
+#[derive(Debug)]
+pub struct EnvVarKey {
+    var: String,
+    // Key handed out last, to tell on refresh whether the variable changed
+    last: RwLock<Option<String>>,
+}
+
+impl EnvVarKey {
+    pub fn new(var: impl Into<String>) -> Self {
+        EnvVarKey {
+            var: var.into(),
+            last: RwLock::new(None),
+        }
+    }
+
+    fn read(&self) -> Result<String, SwarmNodeError> {
+        match env::var(&self.var) {
+            Ok(key) => non_empty(key),
+            Err(env::VarError::NotPresent) => Err(SwarmNodeError::ApiKeyNotSet),
+            Err(e) => Err(SwarmNodeError::Auth(format!("{}: {}", self.var, e))),
+        }
+    }
+}
+
+impl Clone for EnvVarKey {
+    fn clone(&self) -> Self {
+        EnvVarKey::new(self.var.clone())
+    }
+}
+
+impl Default for EnvVarKey {
+    fn default() -> Self {
+        EnvVarKey::new(API_KEY_ENV_VAR)
+    }
+}
+
+impl AuthProvider for EnvVarKey {
+    fn api_key(&self) -> BoxFuture<'_, Result<String, SwarmNodeError>> {
+        let key = self.read();
+        if let Ok(key) = &key {
+            *self.last.write().unwrap() = Some(key.clone());
+        }
+        future::ready(key).boxed()
+    }
+
+    fn refresh(&self) -> BoxFuture<'_, Result<bool, SwarmNodeError>> {
+        let changed = self
+            .read()
+            .map(|key| self.last.read().unwrap().as_deref() != Some(key.as_str()));
+        future::ready(changed).boxed()
+    }
+}
+
+// API key read from a file, e.g. a mounted Kubernetes secret. Surrounding
+// whitespace is ignored.
+pub struct FileKey {
+    path: PathBuf,
+    cached: RwLock<Option<String>>,
+}
+
+impl FileKey {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileKey {
+            path: path.into(),
+            cached: RwLock::new(None),
+        }
+    }
+}
+
+impl fmt::Debug for FileKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileKey").field("path", &self.path).finish()
+    }
+}
+
+impl FileKey {
+    async fn load(&self) -> Result<String, SwarmNodeError> {
+        let key = tokio::fs::read_to_string(&self.path).await.map_err(|e| {
+            SwarmNodeError::Auth(format!("Failed to read {}: {}", self.path.display(), e))
+        })?;
+        non_empty(key.trim().to_string())
+    }
+}
+
+impl AuthProvider for FileKey {
+    fn api_key(&self) -> BoxFuture<'_, Result<String, SwarmNodeError>> {
+        cached_or_load(&self.cached, self.load()).boxed()
+    }
+
+    fn refresh(&self) -> BoxFuture<'_, Result<bool, SwarmNodeError>> {
+        reload(&self.cached, self.load()).boxed()
+    }
+}
+
+// API key printed on stdout by an external command, e.g. a secrets manager
+// CLI. Surrounding whitespace is ignored.
+pub struct CommandKey {
+    program: String,
+    args: Vec<String>,
+    cached: RwLock<Option<String>>,
+}
+
+impl CommandKey {
+    pub fn new<I, S>(program: impl Into<String>, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        CommandKey {
+            program: program.into(),
+            args: args.into_iter().map(Into::into).collect(),
+            cached: RwLock::new(None),
+        }
+    }
+}
+
+impl fmt::Debug for CommandKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CommandKey")
+            .field("program", &self.program)
+            .field("args", &self.args)
+            .finish()
+    }
+}
+
+impl CommandKey {
+    async fn load(&self) -> Result<String, SwarmNodeError> {
+        let output = Command::new(&self.program)
+            .args(&self.args)
+            .kill_on_drop(true)
+            .output()
+            .await
+            .map_err(|e| SwarmNodeError::Auth(format!("Failed to run {}: {}", self.program, e)))?;
+
+        if !output.status.success() {
+            return Err(SwarmNodeError::Auth(format!(
+                "{} exited with {}",
+                self.program, output.status
+            )));
+        }
+
+        let key = String::from_utf8(output.stdout).map_err(|_| {
+            SwarmNodeError::Auth(format!("{} printed a non UTF-8 key", self.program))
+        })?;
+        non_empty(key.trim().to_string())
+    }
+}
+
+impl AuthProvider for CommandKey {
+    fn api_key(&self) -> BoxFuture<'_, Result<String, SwarmNodeError>> {
+        cached_or_load(&self.cached, self.load()).boxed()
+    }
+
+    fn refresh(&self) -> BoxFuture<'_, Result<bool, SwarmNodeError>> {
+        reload(&self.cached, self.load()).boxed()
+    }
+}
+
+fn non_empty(key: String) -> Result<String, SwarmNodeError> {
+    if key.is_empty() {
+        Err(SwarmNodeError::ApiKeyNotSet)
+    } else {
+        Ok(key)
+    }
+}
+
+async fn cached_or_load(
+    cached: &RwLock<Option<String>>,
+    load: impl Future<Output = Result<String, SwarmNodeError>>,
+) -> Result<String, SwarmNodeError> {
+    if let Some(key) = cached.read().unwrap().clone() {
+        return Ok(key);
+    }
+
+    let key = load.await?;
+    *cached.write().unwrap() = Some(key.clone());
+    Ok(key)
+}
+
+// Load the key again and cache it. Returns whether it differs from the one
+// cached before.
+async fn reload(
+    cached: &RwLock<Option<String>>,
+    load: impl Future<Output = Result<String, SwarmNodeError>>,
+) -> Result<bool, SwarmNodeError> {
+    let key = load.await?;
+    let previous = cached.write().unwrap().replace(key.clone());
+    Ok(previous.as_deref() != Some(key.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn env_var_key_changes_only_when_the_variable_does() {
+        let var = "SWARMNODE_TEST_ENV_VAR_KEY";
+        let key = EnvVarKey::new(var);
+        assert!(matches!(
+            key.api_key().await,
+            Err(SwarmNodeError::ApiKeyNotSet)
+        ));
+
+        env::set_var(var, "first");
+        assert_eq!(key.api_key().await.unwrap(), "first");
+        assert!(!key.refresh().await.unwrap());
+
+        env::set_var(var, "second");
+        assert!(key.refresh().await.unwrap());
+        assert_eq!(key.api_key().await.unwrap(), "second");
+        assert!(!key.refresh().await.unwrap());
+        env::remove_var(var);
+    }
+
+    #[tokio::test]
+    async fn file_key_is_cached_until_refreshed() {
+        let path = env::temp_dir().join(format!("swarmnode-key-{}", Uuid::new_v4()));
+        let key = FileKey::new(&path);
+        assert!(matches!(key.api_key().await, Err(SwarmNodeError::Auth(_))));
+
+        std::fs::write(&path, " first\n").unwrap();
+        assert_eq!(key.api_key().await.unwrap(), "first");
+
+        std::fs::write(&path, "second\n").unwrap();
+        assert_eq!(key.api_key().await.unwrap(), "first");
+        assert!(key.refresh().await.unwrap());
+        assert_eq!(key.api_key().await.unwrap(), "second");
+        assert!(!key.refresh().await.unwrap());
+
+        std::fs::write(&path, "\n").unwrap();
+        assert!(matches!(
+            key.refresh().await,
+            Err(SwarmNodeError::ApiKeyNotSet)
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn command_key_reads_stdout() {
+        let key = CommandKey::new("sh", ["-c", "echo '  from-command  '"]);
+        assert_eq!(key.api_key().await.unwrap(), "from-command");
+        assert!(!key.refresh().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn command_key_fails_on_errors_and_empty_output() {
+        let key = CommandKey::new("sh", ["-c", "echo secret; exit 3"]);
+        assert!(matches!(key.api_key().await, Err(SwarmNodeError::Auth(_))));
+
+        let key = CommandKey::new("true", Vec::<String>::new());
+        assert!(matches!(
+            key.api_key().await,
+            Err(SwarmNodeError::ApiKeyNotSet)
+        ));
+
+        let key = CommandKey::new("swarmnode-no-such-command", Vec::<String>::new());
+        assert!(matches!(key.api_key().await, Err(SwarmNodeError::Auth(_))));
+    }
+}
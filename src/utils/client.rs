@@ -1,156 +1,868 @@
-use crate::{get_api_base, get_api_key};
+use crate::resources::agent::Agents;
+use crate::resources::agent_builder_job::AgentBuilderJobs;
+use crate::resources::agent_executor_cron_job::AgentExecutorCronJobs;
+use crate::resources::agent_executor_job::AgentExecutorJobs;
+use crate::resources::build::Builds;
+use crate::resources::execution::Executions;
+use crate::resources::store::Stores;
+use crate::utils::auth::{AuthProvider, EnvVarKey, StaticKey};
+use crate::utils::config::{
+    get_api_base, get_api_key, get_api_version, get_ws_base_url, Endpoint, HttpConfig,
+    SwarmNodeConfig,
+};
+use crate::utils::middleware::{
+    IncomingResponse, Middleware, OutgoingRequest, RequestKind, SyntheticResponse,
+};
+use crate::utils::retry::{is_idempotent, retry_after, RetryPolicy};
+use crate::utils::trace;
 use async_stream::stream;
 use futures_util::StreamExt;
+use lazy_static::lazy_static;
 use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
 use reqwest::Client as ReqwestClient;
-use reqwest::Response;
-use serde::Deserialize;
+use reqwest::{Method, RequestBuilder, Response, StatusCode, Url};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::error::Error;
 use std::fmt;
-use tokio_tungstenite::connect_async;
-
-#[derive(Debug)]
-pub enum SwarmNodeError {
-    BadRequest(String),
-    Unauthenticated(String),
-    NotFound(String),
-    ApiKeyNotSet,
-    Other(String),
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest};
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use uuid::Uuid;
+
+pub use super::error::{ApiError, SwarmNodeError};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+lazy_static! {
+    // Connection pool shared by every client created through `global()`,
+    // built on first use
+    static ref DEFAULT_HTTP_CLIENT: Mutex<Option<ReqwestClient>> = Mutex::new(None);
+}
+
+// Per-call settings. The resource handles set the timeout, e.g.
+// `client.stores().timeout(..)`, and create calls add an idempotency key.
+#[derive(Debug, Clone, Default)]
+pub struct RequestOptions {
+    // Only meant for create calls: it is reused across retries and makes
+    // `send` treat the request as safe to repeat
+    pub idempotency_key: Option<String>,
+    // Overrides the client's total request timeout
+    pub timeout: Option<Duration>,
 }
 
-impl fmt::Display for SwarmNodeError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            SwarmNodeError::BadRequest(ref msg) => write!(f, "Bad Request: {}", msg),
-            SwarmNodeError::Unauthenticated(ref msg) => write!(f, "Unauthenticated: {}", msg),
-            SwarmNodeError::NotFound(ref msg) => write!(f, "Not Found: {}", msg),
-            SwarmNodeError::ApiKeyNotSet => write!(f, "API Key not set"),
-            SwarmNodeError::Other(ref msg) => write!(f, "Other Error: {}", msg),
+impl RequestOptions {
+    // Options for a create call. The idempotency key, given on the create
+    // arguments or else a fresh one, is sent with every attempt so that the
+    // server can tell a retry from a new create. Keep the key to repeat a
+    // create that failed with `SwarmNodeError::OutcomeUnknown`, which carries
+    // it: the server will not create a duplicate or run the agent twice.
+    pub fn for_create(&self, idempotency_key: Option<&str>) -> Self {
+        RequestOptions {
+            idempotency_key: Some(
+                idempotency_key
+                    .map(str::to_string)
+                    .unwrap_or_else(|| Uuid::new_v4().to_string()),
+            ),
+            ..self.clone()
         }
     }
+
+    fn apply(&self, mut request: RequestBuilder) -> RequestBuilder {
+        if let Some(key) = &self.idempotency_key {
+            request = request.header(IDEMPOTENCY_KEY_HEADER, key);
+        }
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+        request
+    }
 }
 
-impl SwarmNodeError {
-    fn from_response(response: &Response) -> Self {
-        let status = response.status();
-        let status_code = status.as_u16();
-        let status_text = status.canonical_reason().unwrap_or("Unknown status");
+// A client bound to one account and one endpoint. Cloning is cheap and the
+// clones share the same HTTP connection pool.
+#[derive(Clone)]
+pub struct SwarmNodeClient {
+    auth: Arc<dyn AuthProvider>,
+    endpoint: Endpoint,
+    max_page_size: Option<u32>,
+    retry: RetryPolicy,
+    middleware: Vec<Arc<dyn Middleware>>,
+    http: ReqwestClient,
+    // WebSocket connections do not go through `http`, so they are bounded
+    // separately
+    ws_connect_timeout: Option<Duration>,
+    listen_timeout: Option<Duration>,
+}
 
-        SwarmNodeError::Other(format!("HTTP {}: {}", status_code, status_text))
+impl fmt::Debug for SwarmNodeClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SwarmNodeClient")
+            .field("auth", &self.auth)
+            .field("endpoint", &self.endpoint)
+            .field("max_page_size", &self.max_page_size)
+            .field("retry", &self.retry)
+            .field("middleware", &self.middleware.len())
+            .field("ws_connect_timeout", &self.ws_connect_timeout)
+            .field("listen_timeout", &self.listen_timeout)
+            .finish()
     }
 }
 
-pub struct SwarmClient;
+impl SwarmNodeClient {
+    pub fn new(config: SwarmNodeConfig) -> Result<Self, SwarmNodeError> {
+        let http = config.http.build_client()?;
+        let client = SwarmNodeClient::with_http_client(config, http);
 
-impl SwarmClient {
-    fn get_http_headers() -> HeaderMap {
-        let api_key = get_api_key().expect("API key not set!"); // Expecting the API key to be set
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            AUTHORIZATION,
-            HeaderValue::from_str(&format!("Bearer {}", api_key)).unwrap(),
-        );
-        headers
+        // Surface a malformed base URL now rather than on the first call
+        client.endpoint.rest_url("")?;
+        client.endpoint.ws_url("")?;
+        Ok(client)
+    }
+
+    // Client that reuses an existing connection pool, so that clients for
+    // several accounts can share connections to the same endpoint. The
+    // REST timeouts in `config.http` are ignored in favour of the pool's own;
+    // `connect_timeout` and `listen_timeout` still bound WebSocket calls.
+    pub fn with_http_client(config: SwarmNodeConfig, http: ReqwestClient) -> Self {
+        SwarmNodeClient {
+            endpoint: Endpoint::from_config(&config),
+            auth: match (config.auth, config.api_key) {
+                (Some(auth), _) => auth,
+                (None, Some(api_key)) => Arc::new(StaticKey::new(api_key)),
+                (None, None) => Arc::new(EnvVarKey::default()),
+            },
+            max_page_size: config.max_page_size,
+            retry: config.retry,
+            middleware: config.middleware,
+            http,
+            ws_connect_timeout: config.http.connect_timeout,
+            listen_timeout: config.http.listen_timeout,
+        }
+    }
+
+    // Client built from the process-wide configuration set with `set_config`.
+    // Fails if the shared connection pool cannot be built, in which case the
+    // next call tries again.
+    pub fn global() -> Result<Self, SwarmNodeError> {
+        let http = {
+            let mut pool = DEFAULT_HTTP_CLIENT.lock().unwrap();
+            match &*pool {
+                Some(http) => http.clone(),
+                None => pool.insert(HttpConfig::default().build_client()?).clone(),
+            }
+        };
+
+        Ok(SwarmNodeClient::with_http_client(
+            SwarmNodeConfig {
+                api_key: get_api_key(),
+                api_base: Some(get_api_base()),
+                ws_base_url: get_ws_base_url(),
+                api_version: Some(get_api_version()),
+                ..Default::default()
+            },
+            http,
+        ))
+    }
+
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
     }
 
-    fn get_ws_headers() -> Vec<(String, String)> {
-        let api_key = get_api_key().expect("API key not set!"); // Expecting the API key to be set
-        vec![("Authorization".to_string(), format!("Bearer {}", api_key))]
+    // See `SwarmNodeConfig::max_page_size`
+    pub fn max_page_size(&self) -> Option<u32> {
+        self.max_page_size
     }
 
-    pub async fn request_action<T: for<'de> Deserialize<'de>>(
+    pub fn agents(&self) -> Agents<'_> {
+        Agents::new(self)
+    }
+
+    pub fn agent_builder_jobs(&self) -> AgentBuilderJobs<'_> {
+        AgentBuilderJobs::new(self)
+    }
+
+    pub fn agent_executor_jobs(&self) -> AgentExecutorJobs<'_> {
+        AgentExecutorJobs::new(self)
+    }
+
+    pub fn agent_executor_cron_jobs(&self) -> AgentExecutorCronJobs<'_> {
+        AgentExecutorCronJobs::new(self)
+    }
+
+    pub fn builds(&self) -> Builds<'_> {
+        Builds::new(self)
+    }
+
+    pub fn executions(&self) -> Executions<'_> {
+        Executions::new(self)
+    }
+
+    pub fn stores(&self) -> Stores<'_> {
+        Stores::new(self)
+    }
+
+    fn parse_method(method: &str) -> Result<reqwest::Method, SwarmNodeError> {
+        method
+            .parse()
+            .map_err(|_| SwarmNodeError::Other(format!("Invalid HTTP method: {}", method)))
+    }
+
+    async fn authorization(&self) -> Result<HeaderValue, SwarmNodeError> {
+        let api_key = self.auth.api_key().await?;
+        let mut value = HeaderValue::from_str(&format!("Bearer {}", api_key))
+            .map_err(|e| SwarmNodeError::Auth(format!("Invalid API key: {}", e)))?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
+
+    // Call an API action. `data` is sent as the JSON body and can be any
+    // serializable value, e.g. a `serde_json::Map` or a `#[derive(Serialize)]`
+    // struct.
+    pub async fn request_action<T, B>(
+        &self,
         method: &str,
         action_path: &str,
         params: Option<HashMap<String, String>>,
-        data: Option<HashMap<String, String>>,
-    ) -> Result<T, SwarmNodeError> {
-        let client = ReqwestClient::new();
-        let url = format!("https://{}/v1/{}", get_api_base(), action_path);
-        let mut request = client
-            .request(method.parse().unwrap(), &url)
-            .headers(SwarmClient::get_http_headers());
+        data: Option<&B>,
+        options: &RequestOptions,
+    ) -> Result<T, SwarmNodeError>
+    where
+        T: for<'de> Deserialize<'de>,
+        B: Serialize + ?Sized,
+    {
+        let url = self.endpoint.rest_url(action_path)?;
+        let mut request = options.apply(self.http.request(Self::parse_method(method)?, url));
 
         if let Some(p) = params {
             request = request.query(&p);
         }
 
         if let Some(d) = data {
-            request = request.json(&d);
+            request = request.json(d);
         }
 
-        let response = request
-            .send()
-            .await
-            .map_err(|e| SwarmNodeError::Other(format!("Request failed: {}", e)))?;
+        let response = self.send(request.build()?).await?;
 
-        if response.status().is_success() {
-            // Deserialize the response body into the type T
-            response
-                .json::<T>()
-                .await
-                .map_err(|e| SwarmNodeError::Other(format!("Failed to parse response body: {}", e)))
-        } else {
-            Err(SwarmNodeError::from_response(&response))
-        }
+        // Deserialize the response body into the type T, treating an
+        // empty body (e.g. 204 No Content) as `null`
+        let body = response.bytes().await?;
+        let body: &[u8] = if body.is_empty() { b"null" } else { &body };
+        serde_json::from_slice(body).map_err(|e| SwarmNodeError::Decode(e.to_string()))
     }
 
-    pub async fn request_url(
+    pub async fn request_url<B: Serialize + ?Sized>(
+        &self,
         method: &str,
         url: &str,
-        data: Option<HashMap<String, String>>,
+        data: Option<&B>,
+        options: &RequestOptions,
     ) -> Result<reqwest::Response, SwarmNodeError> {
-        let client = ReqwestClient::new();
-        let mut request = client
-            .request(method.parse().unwrap(), url)
-            .headers(SwarmClient::get_http_headers());
+        let mut request = options.apply(self.http.request(Self::parse_method(method)?, url));
 
         if let Some(d) = data {
-            request = request.json(&d);
+            request = request.json(d);
         }
 
-        let response = request
-            .send()
-            .await
-            .map_err(|e| SwarmNodeError::Other(format!("Request failed: {}", e)))?;
+        self.send(request.build()?).await
+    }
+
+    // Send a request, retrying it according to the client's retry policy.
+    // Only 2xx responses are returned, anything else becomes an error.
+    async fn send(&self, request: reqwest::Request) -> Result<Response, SwarmNodeError> {
+        let idempotency_key = request
+            .headers()
+            .get(IDEMPOTENCY_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string());
+        let idempotent = is_idempotent(request.method()) || idempotency_key.is_some();
+
+        let method = request.method().clone();
+        let span = trace::request_span(&method, request.url(), &self.endpoint.api_version);
+        let started = Instant::now();
+        let result = trace::instrument(&span, self.send_with_retries(request, idempotent)).await;
+
+        let result = match result {
+            Err(error) if !is_idempotent(&method) && error.may_have_been_processed() => {
+                Err(SwarmNodeError::OutcomeUnknown {
+                    idempotency_key,
+                    source: Box::new(error),
+                })
+            }
+            result => result,
+        };
+
+        trace::finished(&span, &result, started.elapsed());
+        result
+    }
+
+    async fn send_with_retries(
+        &self,
+        request: reqwest::Request,
+        idempotent: bool,
+    ) -> Result<Response, SwarmNodeError> {
+        let started = Instant::now();
+        let mut attempt = 1;
+        let mut refreshed = false;
+
+        loop {
+            let mut attempt_request = request.try_clone().ok_or_else(|| {
+                SwarmNodeError::Other("Request body cannot be retried".to_string())
+            })?;
+            attempt_request
+                .headers_mut()
+                .insert(AUTHORIZATION, self.authorization().await?);
 
-        if response.status().is_success() {
-            Ok(response)
-        } else {
-            Err(SwarmNodeError::from_response(&response))
+            let (error, retry_after) = match self.execute(attempt_request, attempt).await {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) => {
+                    let retry_after = retry_after(response.headers());
+                    (SwarmNodeError::from_response(response).await, retry_after)
+                }
+                Err(err) => (err, None),
+            };
+
+            // A rejected key may have been rotated: reload it and, if it
+            // changed, try once more without counting it as a retry
+            if error.status() == Some(StatusCode::UNAUTHORIZED) && !refreshed {
+                refreshed = true;
+                if self.auth.refresh().await? {
+                    continue;
+                }
+            }
+
+            if attempt >= self.retry.max_attempts || !self.retry.should_retry(&error, idempotent) {
+                return Err(error);
+            }
+
+            // A server asking for a longer wait than `max_delay` is not
+            // waited on for longer than that
+            let delay = match retry_after {
+                Some(delay) => delay.min(self.retry.max_delay),
+                None => self.retry.backoff(attempt),
+            };
+            if let Some(deadline) = self.retry.deadline {
+                if started.elapsed() + delay > deadline {
+                    return Err(error);
+                }
+            }
+
+            trace::retrying(attempt, delay, &error);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
         }
     }
 
-    // Listen to a specific execution via WebSocket
-    pub async fn listen_to_execution(address: &str) -> Result<String, Box<dyn Error>> {
-        let api_base = get_api_base();
-        let headers = SwarmClient::get_ws_headers();
-        let url = format!("wss://{}/ws/v1/execution/{}/", api_base, address);
+    async fn connect_ws(&self, url: &Url) -> Result<WsStream, SwarmNodeError> {
+        let mut attempt = 1;
+        let mut refreshed = false;
 
-        let (mut ws_stream, _) = connect_async(url)
-            .await
-            .expect("Failed to connect to WebSocket");
+        loop {
+            match self.handshake(url, attempt).await {
+                Err(error) if error.status() == Some(StatusCode::UNAUTHORIZED) && !refreshed => {
+                    refreshed = true;
+                    if !self.auth.refresh().await? {
+                        return Err(error);
+                    }
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // Run one WebSocket handshake through the middleware chain
+    async fn handshake(&self, url: &Url, attempt: u32) -> Result<WsStream, SwarmNodeError> {
+        let mut request = url.as_str().into_client_request()?;
+        request
+            .headers_mut()
+            .insert(AUTHORIZATION, self.authorization().await?);
+
+        let started = Instant::now();
+        let (ran, synthetic) = self.run_on_request(
+            RequestKind::WebSocket,
+            &Method::GET,
+            url,
+            request.headers_mut(),
+            attempt,
+        );
+        let is_synthetic = synthetic.is_some();
+        trace::attempt_started(attempt, request.headers());
+
+        let (result, status, headers) = match synthetic {
+            Some(response) => {
+                let error = ApiError::new(response.status, &response.headers, &response.body);
+                (
+                    Err(error.into()),
+                    Some(response.status),
+                    Some(response.headers),
+                )
+            }
+            None => {
+                let connect = async { Ok(connect_async(request).await?) };
+                match with_timeout(self.ws_connect_timeout, "the WebSocket handshake", connect)
+                    .await
+                {
+                    Ok((ws_stream, response)) => (
+                        Ok(ws_stream),
+                        Some(response.status()),
+                        Some(response.headers().clone()),
+                    ),
+                    Err(error) => {
+                        let status = error.status();
+                        (Err(error), status, None)
+                    }
+                }
+            }
+        };
+
+        trace::attempt_finished(attempt, status, result.as_ref().err(), started.elapsed());
+
+        let report = IncomingResponse {
+            kind: RequestKind::WebSocket,
+            method: &Method::GET,
+            url,
+            attempt,
+            status,
+            headers: headers.as_ref(),
+            error: result.as_ref().err(),
+            elapsed: started.elapsed(),
+            synthetic: is_synthetic,
+        };
+        for middleware in self.middleware[..ran].iter().rev() {
+            middleware.on_response(&report);
+        }
+
+        result
+    }
+
+    // Run one REST attempt through the middleware chain
+    async fn execute(
+        &self,
+        mut request: reqwest::Request,
+        attempt: u32,
+    ) -> Result<Response, SwarmNodeError> {
+        let method = request.method().clone();
+        let url = request.url().clone();
+
+        let started = Instant::now();
+        let (ran, synthetic) = self.run_on_request(
+            RequestKind::Rest,
+            &method,
+            &url,
+            request.headers_mut(),
+            attempt,
+        );
+        let is_synthetic = synthetic.is_some();
+        trace::attempt_started(attempt, request.headers());
+
+        let result = match synthetic {
+            Some(response) => Ok(response.into_response()),
+            None => self
+                .http
+                .execute(request)
+                .await
+                .map_err(SwarmNodeError::from),
+        };
 
-        let message = ws_stream.next().await.unwrap()?;
+        let status = match &result {
+            Ok(response) => Some(response.status()),
+            Err(error) => error.status(),
+        };
+        trace::attempt_finished(attempt, status, result.as_ref().err(), started.elapsed());
+
+        let report = IncomingResponse {
+            kind: RequestKind::Rest,
+            method: &method,
+            url: &url,
+            attempt,
+            status,
+            headers: result.as_ref().ok().map(|response| response.headers()),
+            error: result.as_ref().err(),
+            elapsed: started.elapsed(),
+            synthetic: is_synthetic,
+        };
+        for middleware in self.middleware[..ran].iter().rev() {
+            middleware.on_response(&report);
+        }
+
+        result
+    }
+
+    // Pass a request through `on_request` of each middleware. Returns how
+    // many middlewares saw it and the response of the one that
+    // short-circuited it, if any.
+    fn run_on_request(
+        &self,
+        kind: RequestKind,
+        method: &Method,
+        url: &Url,
+        headers: &mut HeaderMap,
+        attempt: u32,
+    ) -> (usize, Option<SyntheticResponse>) {
+        let mut request = OutgoingRequest {
+            kind,
+            method,
+            url,
+            headers,
+            attempt,
+        };
+
+        for (index, middleware) in self.middleware.iter().enumerate() {
+            if let Some(response) = middleware.on_request(&mut request) {
+                return (index + 1, Some(response));
+            }
+        }
+        (self.middleware.len(), None)
+    }
+
+    // Connect to a WebSocket path inside a session span
+    async fn open_session(&self, path: &str) -> Result<(WsStream, trace::Span), SwarmNodeError> {
+        let url = self.endpoint.ws_url(path)?;
+        let span = trace::websocket_span(&url, &self.endpoint.api_version);
+
+        let started = Instant::now();
+        let result = trace::instrument(&span, self.connect_ws(&url)).await;
+        trace::finished(&span, &result, started.elapsed());
+
+        Ok((result?, span))
+    }
+
+    // Listen to a specific execution via WebSocket
+    pub async fn listen_to_execution(&self, address: &str) -> Result<String, SwarmNodeError> {
+        let (mut ws_stream, span) = self
+            .open_session(&format!("execution/{}/", address))
+            .await?;
+
+        let next = async {
+            Ok(ws_stream
+                .next()
+                .await
+                .ok_or(tungstenite::Error::ConnectionClosed)??)
+        };
+        let what = format!("execution {} to finish", address);
+        let message =
+            trace::instrument(&span, with_timeout(self.listen_timeout, &what, next)).await?;
+        trace::message_received(&span, message.len());
         Ok(message.to_string())
     }
 
     // Listen to execution stream via WebSocket
     pub async fn listen_to_execution_stream(
+        &self,
         address: &str,
-    ) -> impl futures_util::Stream<Item = Result<String, Box<dyn Error>>> {
-        let api_base = get_api_base();
-        let headers = SwarmClient::get_ws_headers();
-        let url = format!("wss://{}/ws/v1/execution-stream/{}/", api_base, address);
+    ) -> Result<impl futures_util::Stream<Item = Result<String, SwarmNodeError>>, SwarmNodeError>
+    {
+        let (mut ws_stream, span) = self
+            .open_session(&format!("execution-stream/{}/", address))
+            .await?;
+
+        Ok(stream! {
+            while let Some(message) = trace::instrument(&span, ws_stream.next()).await {
+                let message = message?;
+                trace::message_received(&span, message.len());
+                yield Ok(message.to_string());
+            }
+            trace::session_closed(&span);
+        })
+    }
+}
 
-        let (mut ws_stream, _) = connect_async(url)
+// Run `future`, failing with `SwarmNodeError::Timeout` if it takes longer than
+// `timeout`. `what` names what was waited for in the error.
+pub(crate) async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    what: &str,
+    future: impl Future<Output = Result<T, SwarmNodeError>>,
+) -> Result<T, SwarmNodeError> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future)
             .await
-            .expect("Failed to connect to WebSocket");
+            .map_err(|_| SwarmNodeError::Timeout(format!("waited {:?} for {}", timeout, what)))?,
+        None => future.await,
+    }
+}
 
-        stream! {
-            while let Some(message) = ws_stream.next().await {
-                yield Ok(message?.to_string());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::middleware::IncomingResponse;
+    use crate::utils::testing::{client, fast_retries, FakeApi, BASE_URL};
+    use futures_util::future::{self, BoxFuture, FutureExt};
+    use reqwest::header::RETRY_AFTER;
+    use serde_json::{json, Value};
+    use std::sync::Mutex;
+
+    // Middleware that logs the calls it gets, and answers the request itself
+    // when `answer` is set
+    struct Recorder {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+        answer: Option<SyntheticResponse>,
+    }
+
+    impl Middleware for Recorder {
+        fn on_request(&self, _request: &mut OutgoingRequest<'_>) -> Option<SyntheticResponse> {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("{} request", self.name));
+            self.answer.clone()
+        }
+
+        fn on_response(&self, response: &IncomingResponse<'_>) {
+            self.log.lock().unwrap().push(format!(
+                "{} response {} synthetic={}",
+                self.name,
+                response.status.unwrap(),
+                response.synthetic
+            ));
+        }
+    }
+
+    fn ok(body: Value) -> SyntheticResponse {
+        SyntheticResponse::json(StatusCode::OK, &body).unwrap()
+    }
+
+    fn status(status: StatusCode) -> SyntheticResponse {
+        SyntheticResponse::json(status, &json!({ "detail": "nope" })).unwrap()
+    }
+
+    async fn get(client: &SwarmNodeClient) -> Result<Value, SwarmNodeError> {
+        client
+            .request_action(
+                "GET",
+                "agents/",
+                None,
+                None::<&Value>,
+                &RequestOptions::default(),
+            )
+            .await
+    }
+
+    #[test]
+    fn global_client_builds_its_pool_once() {
+        let client = SwarmNodeClient::global().unwrap();
+        assert!(DEFAULT_HTTP_CLIENT.lock().unwrap().is_some());
+        assert_eq!(client.endpoint().api_version, get_api_version());
+        assert!(SwarmNodeClient::global().is_ok());
+    }
+
+    #[tokio::test]
+    async fn request_action_decodes_a_synthetic_response() {
+        let api = FakeApi::new(|_| ok(json!({ "id": "a1" })));
+        let client = client(vec![api.clone()], RetryPolicy::none());
+
+        assert_eq!(get(&client).await.unwrap(), json!({ "id": "a1" }));
+
+        let seen = api.seen();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0].method, Method::GET);
+        assert_eq!(seen[0].url.as_str(), "http://api.test/v1/agents/");
+        assert_eq!(seen[0].headers[AUTHORIZATION], "Bearer test-key");
+    }
+
+    #[tokio::test]
+    async fn responses_run_in_reverse_for_the_middlewares_that_saw_the_request() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let recorder = |name, answer| -> Arc<dyn Middleware> {
+            Arc::new(Recorder {
+                name,
+                log: log.clone(),
+                answer,
+            })
+        };
+        let client = client(
+            vec![
+                recorder("outer", None),
+                recorder("inner", Some(ok(json!(null)))),
+                recorder("skipped", None),
+            ],
+            RetryPolicy::none(),
+        );
+
+        get(&client).await.unwrap();
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                "outer request",
+                "inner request",
+                "inner response 200 OK synthetic=true",
+                "outer response 200 OK synthetic=true",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn attempt_counts_up_across_retries() {
+        let api = FakeApi::new(|seen| match seen.attempt {
+            1 | 2 => status(StatusCode::SERVICE_UNAVAILABLE),
+            _ => ok(json!([])),
+        });
+        let client = client(vec![api.clone()], fast_retries(3));
+
+        assert_eq!(get(&client).await.unwrap(), json!([]));
+        let attempts: Vec<_> = api.seen().iter().map(|seen| seen.attempt).collect();
+        assert_eq!(attempts, [1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn unavailable_is_retried_until_it_succeeds() {
+        let api = FakeApi::new(|seen| match seen.attempt {
+            1 => {
+                // Far longer than the test could wait
+                let mut response = status(StatusCode::SERVICE_UNAVAILABLE);
+                response
+                    .headers
+                    .insert(RETRY_AFTER, HeaderValue::from_static("86400"));
+                response
+            }
+            _ => ok(json!({ "ok": true })),
+        });
+        let client = client(vec![api.clone()], fast_retries(3));
+
+        let started = Instant::now();
+        assert_eq!(get(&client).await.unwrap(), json!({ "ok": true }));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(api.seen().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn every_attempt_of_a_create_carries_the_same_idempotency_key() {
+        let api = FakeApi::new(|_| status(StatusCode::BAD_GATEWAY));
+        let client = client(vec![api.clone()], fast_retries(3));
+        let options = RequestOptions::default().for_create(None);
+
+        let error = client
+            .request_url(
+                "POST",
+                "http://api.test/v1/agents/create/",
+                Some(&json!({})),
+                &options,
+            )
+            .await
+            .unwrap_err();
+
+        let keys: Vec<_> = api
+            .seen()
+            .iter()
+            .map(|seen| {
+                seen.headers[IDEMPOTENCY_KEY_HEADER]
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(keys.len(), 3);
+        assert!(keys
+            .iter()
+            .all(|key| Some(key) == options.idempotency_key.as_ref()));
+
+        match error {
+            SwarmNodeError::OutcomeUnknown {
+                idempotency_key,
+                source,
+            } => {
+                assert_eq!(idempotency_key, options.idempotency_key);
+                assert_eq!(source.status(), Some(StatusCode::BAD_GATEWAY));
+            }
+            error => panic!("expected OutcomeUnknown, got {:?}", error),
+        }
+    }
+
+    // Key provider whose key changes on every refresh, if `rotates`
+    #[derive(Debug)]
+    struct RotatingKey {
+        rotates: bool,
+        refreshes: Mutex<u32>,
+    }
+
+    impl AuthProvider for RotatingKey {
+        fn api_key(&self) -> BoxFuture<'_, Result<String, SwarmNodeError>> {
+            let key = format!("key-{}", self.refreshes.lock().unwrap());
+            future::ready(Ok(key)).boxed()
+        }
+
+        fn refresh(&self) -> BoxFuture<'_, Result<bool, SwarmNodeError>> {
+            if self.rotates {
+                *self.refreshes.lock().unwrap() += 1;
             }
+            future::ready(Ok(self.rotates)).boxed()
         }
     }
+
+    fn client_with_key(api: Arc<FakeApi>, rotates: bool) -> SwarmNodeClient {
+        SwarmNodeClient::new(SwarmNodeConfig {
+            auth: Some(Arc::new(RotatingKey {
+                rotates,
+                refreshes: Mutex::new(0),
+            })),
+            base_url: Some(BASE_URL.to_string()),
+            retry: fast_retries(3),
+            middleware: vec![api],
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn keys_sent(api: &FakeApi) -> Vec<String> {
+        api.seen()
+            .iter()
+            .map(|seen| seen.headers[AUTHORIZATION].to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn unauthorized_is_resent_once_with_the_refreshed_key() {
+        let api = FakeApi::new(|seen| match seen.headers[AUTHORIZATION].to_str().unwrap() {
+            "Bearer key-0" => status(StatusCode::UNAUTHORIZED),
+            _ => ok(json!({})),
+        });
+        get(&client_with_key(api.clone(), true)).await.unwrap();
+        assert_eq!(keys_sent(&api), ["Bearer key-0", "Bearer key-1"]);
+
+        // A key that is rejected again is not refreshed a second time
+        let api = FakeApi::new(|_| status(StatusCode::UNAUTHORIZED));
+        let error = get(&client_with_key(api.clone(), true)).await.unwrap_err();
+        assert_eq!(error.status(), Some(StatusCode::UNAUTHORIZED));
+        assert_eq!(keys_sent(&api), ["Bearer key-0", "Bearer key-1"]);
+    }
+
+    #[tokio::test]
+    async fn unauthorized_is_not_resent_when_the_key_did_not_change() {
+        let api = FakeApi::new(|_| status(StatusCode::UNAUTHORIZED));
+        let error = get(&client_with_key(api.clone(), false)).await.unwrap_err();
+        assert!(
+            matches!(error, SwarmNodeError::Unauthenticated(_)),
+            "{:?}",
+            error
+        );
+        assert_eq!(keys_sent(&api), ["Bearer key-0"]);
+    }
+
+    #[tokio::test]
+    async fn post_is_not_retried_on_bad_gateway() {
+        let api = FakeApi::new(|_| status(StatusCode::BAD_GATEWAY));
+        let client = client(vec![api.clone()], fast_retries(3));
+
+        let error = client
+            .request_url(
+                "POST",
+                "http://api.test/v1/agents/create/",
+                Some(&json!({})),
+                &RequestOptions::default(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(error.status(), Some(StatusCode::BAD_GATEWAY));
+        assert_eq!(api.seen().len(), 1);
+
+        // The same failure on a GET is retried
+        get(&client).await.unwrap_err();
+        assert_eq!(api.seen().len(), 4);
+    }
 }
@@ -1,26 +1,202 @@
-use std::env;
 use lazy_static::lazy_static;
-use std::sync::RwLock;
+use std::env;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use reqwest::Url;
+
+use super::auth::{AuthProvider, API_KEY_ENV_VAR};
+use super::error::SwarmNodeError;
+use super::middleware::Middleware;
+use super::retry::RetryPolicy;
+
+pub const DEFAULT_API_BASE: &str = "api.swarmnode.ai";
+pub const DEFAULT_API_VERSION: &str = "v1";
 
 lazy_static! {
     // Default API base URL
-    pub static ref API_BASE: RwLock<String> = RwLock::new("api.swarmnode.ai".to_string());
+    pub static ref API_BASE: RwLock<String> = RwLock::new(DEFAULT_API_BASE.to_string());
 
     // API key, fetched from environment variable or manually set
     pub static ref API_KEY: RwLock<Option<String>> = RwLock::new(None);
+
+    // WebSocket base URL, derived from the API base when unset
+    pub static ref WS_BASE_URL: RwLock<Option<String>> = RwLock::new(None);
+
+    // API version segment added to every URL
+    pub static ref API_VERSION: RwLock<String> = RwLock::new(DEFAULT_API_VERSION.to_string());
 }
 
 // Define a struct for the configuration
+#[derive(Clone, Default)]
 pub struct SwarmNodeConfig {
     pub api_key: Option<String>,
+    // Where the API key comes from. Takes precedence over `api_key`; when
+    // both are unset the key is read from `SWARMNODE_API_KEY`.
+    pub auth: Option<Arc<dyn AuthProvider>>,
+    // Host of the API, e.g. `api.swarmnode.ai`, reached over HTTPS
     pub api_base: Option<String>,
+    // Full REST base URL with scheme, port and path prefix, e.g.
+    // `http://localhost:8000/proxy`. Takes precedence over `api_base`.
+    pub base_url: Option<String>,
+    // Full WebSocket base URL, e.g. `ws://localhost:8001/ws`. Derived from
+    // the REST base URL (`https` -> `wss`, path + `/ws`) when unset.
+    pub ws_base_url: Option<String>,
+    // Version segment added after the base URLs, `v1` by default
+    pub api_version: Option<String>,
+    // Largest `page_size` list calls may ask for. The API documentation does
+    // not state a maximum, so by default any positive size is sent and the
+    // server applies its own limit; set this to reject larger sizes before
+    // the request is sent.
+    pub max_page_size: Option<u32>,
+    pub retry: RetryPolicy,
+    pub http: HttpConfig,
+    // Hooks run around every REST call and WebSocket handshake, see
+    // `Middleware`
+    pub middleware: Vec<Arc<dyn Middleware>>,
+}
+
+// Connection pool and timeout settings of the underlying HTTP client.
+// `None` disables the corresponding timeout.
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    // Time allowed to establish a connection, including the TLS handshake.
+    // Also bounds WebSocket handshakes.
+    pub connect_timeout: Option<Duration>,
+    // Time allowed between two reads of the response
+    pub read_timeout: Option<Duration>,
+    // Time allowed for a whole request, from sending it to reading the body
+    pub timeout: Option<Duration>,
+    // Time `listen_to_execution` waits for the execution to report back
+    pub listen_timeout: Option<Duration>,
+    pub pool_idle_timeout: Option<Duration>,
+    pub pool_max_idle_per_host: usize,
+    pub tcp_keepalive: Option<Duration>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            connect_timeout: Some(Duration::from_secs(10)),
+            read_timeout: Some(Duration::from_secs(30)),
+            timeout: Some(Duration::from_secs(60)),
+            listen_timeout: Some(Duration::from_secs(15 * 60)),
+            pool_idle_timeout: Some(Duration::from_secs(90)),
+            pool_max_idle_per_host: 32,
+            tcp_keepalive: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+impl HttpConfig {
+    pub fn build_client(&self) -> Result<reqwest::Client, reqwest::Error> {
+        let mut builder = reqwest::Client::builder()
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .tcp_keepalive(self.tcp_keepalive);
+
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.read_timeout {
+            builder = builder.read_timeout(timeout);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+
+        builder.build()
+    }
+}
+
+// Where the REST and WebSocket APIs live. URLs are built as
+// `{base_url}/{api_version}/{path}` and `{ws_base_url}/{api_version}/{path}`.
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    pub base_url: String,
+    pub ws_base_url: Option<String>,
+    pub api_version: String,
+}
+
+impl Endpoint {
+    pub fn from_config(config: &SwarmNodeConfig) -> Self {
+        let base_url = match (&config.base_url, &config.api_base) {
+            (Some(base_url), _) => base_url.clone(),
+            // `api_base` used to be a bare host; accept full URLs there too
+            (None, Some(api_base)) if api_base.contains("://") => api_base.clone(),
+            (None, Some(api_base)) => format!("https://{}", api_base),
+            (None, None) => format!("https://{}", DEFAULT_API_BASE),
+        };
+
+        Endpoint {
+            base_url,
+            ws_base_url: config.ws_base_url.clone(),
+            api_version: config
+                .api_version
+                .clone()
+                .unwrap_or_else(|| DEFAULT_API_VERSION.to_string()),
+        }
+    }
+
+    // REST URL of an API path, e.g. `agents/` -> `https://api.swarmnode.ai/v1/agents/`
+    pub fn rest_url(&self, path: &str) -> Result<Url, SwarmNodeError> {
+        let base = Self::parse_base(&self.base_url, &["http", "https"])?;
+        self.join(base, path)
+    }
+
+    // WebSocket URL of an API path, e.g. `execution/<address>/` ->
+    // `wss://api.swarmnode.ai/ws/v1/execution/<address>/`
+    pub fn ws_url(&self, path: &str) -> Result<Url, SwarmNodeError> {
+        let base = match &self.ws_base_url {
+            Some(ws_base_url) => Self::parse_base(ws_base_url, &["ws", "wss"])?,
+            None => {
+                let mut base = Self::parse_base(&self.base_url, &["http", "https"])?;
+                let scheme = if base.scheme() == "https" {
+                    "wss"
+                } else {
+                    "ws"
+                };
+                base.set_scheme(scheme).map_err(|_| {
+                    SwarmNodeError::Config(format!("Invalid base URL: {}", self.base_url))
+                })?;
+                base.join("ws/")
+                    .map_err(|e| SwarmNodeError::Config(e.to_string()))?
+            }
+        };
+        self.join(base, path)
+    }
+
+    fn parse_base(base: &str, schemes: &[&str]) -> Result<Url, SwarmNodeError> {
+        let mut url = Url::parse(base)
+            .map_err(|e| SwarmNodeError::Config(format!("Invalid base URL {}: {}", base, e)))?;
+
+        if !schemes.contains(&url.scheme()) {
+            return Err(SwarmNodeError::Config(format!(
+                "Invalid base URL {}: scheme must be one of {}",
+                base,
+                schemes.join(", ")
+            )));
+        }
+
+        // Make the path prefix a directory so that joining keeps it
+        if !url.path().ends_with('/') {
+            let path = format!("{}/", url.path());
+            url.set_path(&path);
+        }
+        Ok(url)
+    }
+
+    fn join(&self, base: Url, path: &str) -> Result<Url, SwarmNodeError> {
+        base.join(&format!("{}/", self.api_version.trim_matches('/')))
+            .and_then(|url| url.join(path.trim_start_matches('/')))
+            .map_err(|e| SwarmNodeError::Config(format!("Invalid URL for {}: {}", path, e)))
+    }
 }
 
 // Function to initialize the API key from the environment (if available)
 pub fn initialize_api_key_from_env() {
-    let api_key = env::var("SWARMNODE_API_KEY").ok();
-    if api_key.is_some() {
-        set_api_key(&api_key.unwrap());
+    if let Ok(api_key) = env::var(API_KEY_ENV_VAR) {
+        set_api_key(&api_key);
     }
 }
 
@@ -36,6 +212,30 @@ pub fn get_api_base() -> String {
     api_base.clone()
 }
 
+// Set the WebSocket base URL
+pub fn set_ws_base_url(base: &str) {
+    let mut ws_base_url = WS_BASE_URL.write().unwrap();
+    *ws_base_url = Some(base.to_string());
+}
+
+// Get the WebSocket base URL
+pub fn get_ws_base_url() -> Option<String> {
+    let ws_base_url = WS_BASE_URL.read().unwrap();
+    ws_base_url.clone()
+}
+
+// Set the API version
+pub fn set_api_version(version: &str) {
+    let mut api_version = API_VERSION.write().unwrap();
+    *api_version = version.to_string();
+}
+
+// Get the API version
+pub fn get_api_version() -> String {
+    let api_version = API_VERSION.read().unwrap();
+    api_version.clone()
+}
+
 // Set the API key manually
 pub fn set_api_key(key: &str) {
     let mut api_key = API_KEY.write().unwrap();
@@ -53,10 +253,152 @@ pub fn set_config(config: SwarmNodeConfig) {
     if let Some(key) = config.api_key {
         set_api_key(&key);
     }
-    if let Some(base) = config.api_base {
+    if let Some(base) = config.base_url.or(config.api_base) {
         set_api_base(&base);
     }
+    if let Some(base) = config.ws_base_url {
+        set_ws_base_url(&base);
+    }
+    if let Some(version) = config.api_version {
+        set_api_version(&version);
+    }
 
     // Optionally, initialize API key from the environment if it's not set manually
     initialize_api_key_from_env();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(base_url: &str) -> Endpoint {
+        Endpoint::from_config(&SwarmNodeConfig {
+            base_url: Some(base_url.to_string()),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn default_endpoint() {
+        let endpoint = Endpoint::from_config(&SwarmNodeConfig::default());
+        assert_eq!(
+            endpoint.rest_url("agents/").unwrap().as_str(),
+            "https://api.swarmnode.ai/v1/agents/"
+        );
+        assert_eq!(
+            endpoint.ws_url("execution/abc/").unwrap().as_str(),
+            "wss://api.swarmnode.ai/ws/v1/execution/abc/"
+        );
+    }
+
+    #[test]
+    fn api_base_accepts_a_host_or_a_url() {
+        let endpoint = |api_base: &str| {
+            Endpoint::from_config(&SwarmNodeConfig {
+                api_base: Some(api_base.to_string()),
+                ..Default::default()
+            })
+        };
+        assert_eq!(endpoint("example.com").base_url, "https://example.com");
+        assert_eq!(
+            endpoint("http://localhost:8000").base_url,
+            "http://localhost:8000"
+        );
+
+        // `base_url` wins over `api_base`
+        let endpoint = Endpoint::from_config(&SwarmNodeConfig {
+            api_base: Some("example.com".to_string()),
+            base_url: Some("http://localhost".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(endpoint.base_url, "http://localhost");
+    }
+
+    #[test]
+    fn path_prefix_is_kept_with_or_without_trailing_slash() {
+        for base_url in [
+            "http://localhost:8000/proxy",
+            "http://localhost:8000/proxy/",
+        ] {
+            let endpoint = endpoint(base_url);
+            assert_eq!(
+                endpoint.rest_url("agents/").unwrap().as_str(),
+                "http://localhost:8000/proxy/v1/agents/"
+            );
+            assert_eq!(
+                endpoint.rest_url("/agents/").unwrap().as_str(),
+                "http://localhost:8000/proxy/v1/agents/"
+            );
+        }
+    }
+
+    #[test]
+    fn api_version_slashes_are_ignored() {
+        let mut endpoint = endpoint("https://example.com/api");
+        endpoint.api_version = "/v2/".to_string();
+        assert_eq!(
+            endpoint.rest_url("stores/").unwrap().as_str(),
+            "https://example.com/api/v2/stores/"
+        );
+        assert_eq!(
+            endpoint.rest_url("").unwrap().as_str(),
+            "https://example.com/api/v2/"
+        );
+    }
+
+    #[test]
+    fn ws_url_is_derived_from_the_rest_base() {
+        assert_eq!(
+            endpoint("https://example.com/proxy")
+                .ws_url("execution-stream/abc/")
+                .unwrap()
+                .as_str(),
+            "wss://example.com/proxy/ws/v1/execution-stream/abc/"
+        );
+        assert_eq!(
+            endpoint("http://localhost:8000")
+                .ws_url("execution/abc/")
+                .unwrap()
+                .as_str(),
+            "ws://localhost:8000/ws/v1/execution/abc/"
+        );
+    }
+
+    #[test]
+    fn explicit_ws_base_url_is_used_as_is() {
+        let endpoint = Endpoint::from_config(&SwarmNodeConfig {
+            base_url: Some("http://localhost:8000".to_string()),
+            ws_base_url: Some("ws://localhost:8001/socket".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(
+            endpoint.ws_url("execution/abc/").unwrap().as_str(),
+            "ws://localhost:8001/socket/v1/execution/abc/"
+        );
+    }
+
+    #[test]
+    fn wrong_schemes_are_rejected() {
+        assert!(matches!(
+            endpoint("ws://localhost").rest_url(""),
+            Err(SwarmNodeError::Config(_))
+        ));
+        assert!(matches!(
+            endpoint("localhost:8000").rest_url(""),
+            Err(SwarmNodeError::Config(_))
+        ));
+        assert!(matches!(
+            endpoint("not a url").rest_url(""),
+            Err(SwarmNodeError::Config(_))
+        ));
+
+        let endpoint = Endpoint::from_config(&SwarmNodeConfig {
+            ws_base_url: Some("https://example.com".to_string()),
+            ..Default::default()
+        });
+        assert!(matches!(
+            endpoint.ws_url(""),
+            Err(SwarmNodeError::Config(_))
+        ));
+    }
+}
@@ -0,0 +1,532 @@
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::error::SwarmNodeError;
+
+const REDACTED: &str = "***";
+
+// Environment variables of an agent. Read from `.env` files with `parse` or
+// `from_file` and sent to the API as `KEY=value` lines. `Debug` and
+// `Display` only show the keys, so printing an `Agent` does not leak secrets.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct EnvVars {
+    vars: BTreeMap<String, String>,
+    // Entries read from the API that are not valid `KEY=value` lines, kept
+    // as they are so that sending the variables back does not drop them
+    unparsed: Vec<String>,
+}
+
+impl EnvVars {
+    pub fn new() -> Self {
+        EnvVars::default()
+    }
+
+    // Parse `.env` content: `KEY=value` lines with optional `export`
+    // prefixes, `#` comments, single-quoted literal values and double-quoted
+    // values with `\n`-style escapes. Quoted values may span several lines.
+    pub fn parse(input: &str) -> Result<Self, SwarmNodeError> {
+        Parser::new(input).parse(true)
+    }
+
+    // Like `parse`, but lines that cannot be parsed are kept in `unparsed`
+    // instead of failing. Used to read what the API returns, which may not
+    // have been written by this crate.
+    fn parse_lenient(input: &str) -> Self {
+        Parser::new(input)
+            .parse(false)
+            .expect("lenient parsing does not fail")
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, SwarmNodeError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            SwarmNodeError::InvalidArgument(format!("Failed to read {}: {}", path.display(), e))
+        })?;
+        EnvVars::parse(&content)
+    }
+
+    // Set a variable, replacing any previous value. Keys are letters, digits
+    // and underscores, not starting with a digit.
+    pub fn insert(
+        &mut self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<(), SwarmNodeError> {
+        let key = key.into();
+        if !is_valid_key(&key) {
+            return Err(SwarmNodeError::InvalidArgument(format!(
+                "Invalid environment variable name: {:?}",
+                key
+            )));
+        }
+        self.vars.insert(key, value.into());
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.vars.get(key).map(String::as_str)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.vars.remove(key)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.vars.keys().map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.vars.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vars.is_empty()
+    }
+
+    // Entries returned by the API that could not be read as `KEY=value`,
+    // exactly as they were received. They may hold secrets.
+    pub fn unparsed(&self) -> impl Iterator<Item = &str> {
+        self.unparsed.iter().map(String::as_str)
+    }
+
+    // The variables in the format the API expects, one `KEY=value` line per
+    // variable. Values that would not survive unquoted are double-quoted.
+    // Unparsed entries are written back unchanged after the variables.
+    pub fn to_env_string(&self) -> String {
+        let mut out = String::new();
+        for (key, value) in &self.vars {
+            out.push_str(&env_line(key, value));
+            out.push('\n');
+        }
+        for entry in &self.unparsed {
+            out.push_str(entry);
+            out.push('\n');
+        }
+        out
+    }
+}
+
+fn env_line(key: &str, value: &str) -> String {
+    let mut out = format!("{}=", key);
+    if needs_quotes(value) {
+        out.push('"');
+        for c in value.chars() {
+            match c {
+                '\\' => out.push_str("\\\\"),
+                '"' => out.push_str("\\\""),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+    } else {
+        out.push_str(value);
+    }
+    out
+}
+
+fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn needs_quotes(value: &str) -> bool {
+    value.is_empty()
+        || value.trim() != value
+        || value
+            .chars()
+            .any(|c| matches!(c, '"' | '\'' | '\\' | '#' | '\n' | '\r' | '\t'))
+}
+
+impl FromStr for EnvVars {
+    type Err = SwarmNodeError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        EnvVars::parse(input)
+    }
+}
+
+impl fmt::Debug for EnvVars {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut map = f.debug_map();
+        map.entries(self.vars.keys().map(|key| (key, REDACTED)));
+        if !self.unparsed.is_empty() {
+            map.entry(&"<unparsed>", &self.unparsed.len());
+        }
+        map.finish()
+    }
+}
+
+impl fmt::Display for EnvVars {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, key) in self.vars.keys().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}={}", key, REDACTED)?;
+        }
+        if !self.unparsed.is_empty() {
+            if !self.vars.is_empty() {
+                f.write_str(", ")?;
+            }
+            write!(f, "<{} unparsed>", self.unparsed.len())?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for EnvVars {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_env_string())
+    }
+}
+
+// Accepts the API's `KEY=value` string as well as a JSON object. Reading
+// never fails on the content: entries that do not parse or have an invalid
+// name end up in `unparsed`.
+impl<'de> Deserialize<'de> for EnvVars {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EnvVarsVisitor;
+
+        impl<'de> Visitor<'de> for EnvVarsVisitor {
+            type Value = EnvVars;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("KEY=value lines or a map of environment variables")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<EnvVars, E> {
+                Ok(EnvVars::parse_lenient(value))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<EnvVars, A::Error> {
+                let mut vars = EnvVars::new();
+                while let Some((key, value)) = map.next_entry::<String, String>()? {
+                    if is_valid_key(&key) {
+                        vars.vars.insert(key, value);
+                    } else {
+                        vars.unparsed.push(env_line(&key, &value));
+                    }
+                }
+                Ok(vars)
+            }
+        }
+
+        deserializer.deserialize_any(EnvVarsVisitor)
+    }
+}
+
+// Line-oriented `.env` parser that lets quoted values run over line breaks
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Parser {
+            input,
+            pos: 0,
+            line: 1,
+        }
+    }
+
+    // When not `strict`, an entry that fails to parse is kept as unparsed
+    // up to the end of its first line, and parsing goes on from the next one
+    fn parse(mut self, strict: bool) -> Result<EnvVars, SwarmNodeError> {
+        let mut vars = EnvVars::new();
+        loop {
+            self.skip_while(|c| c.is_whitespace());
+            match self.peek() {
+                None => return Ok(vars),
+                Some('#') => {
+                    self.skip_while(|c| c != '\n');
+                    continue;
+                }
+                Some(_) => {}
+            }
+
+            let (start, line) = (self.pos, self.line);
+            match self.entry() {
+                Ok((key, value)) => {
+                    vars.vars.insert(key, value);
+                }
+                Err(error) if strict => return Err(error),
+                Err(_) => {
+                    let end = self.input[start..]
+                        .find('\n')
+                        .map_or(self.input.len(), |i| start + i);
+                    vars.unparsed
+                        .push(self.input[start..end].trim_end().to_string());
+                    self.pos = end;
+                    self.line = line;
+                }
+            }
+        }
+    }
+
+    fn entry(&mut self) -> Result<(String, String), SwarmNodeError> {
+        let line = self.line;
+        let key = self.take_while(|c| c != '=' && c != '\n');
+        let key = strip_export(key.trim()).to_string();
+        if self.next_char() != Some('=') {
+            return Err(self.error(line, &format!("expected KEY=value, got {:?}", key)));
+        }
+        if !is_valid_key(&key) {
+            return Err(self.error(line, &format!("invalid variable name {:?}", key)));
+        }
+
+        self.skip_while(|c| c == ' ' || c == '\t');
+        let value = match self.peek() {
+            Some('\'') => self.single_quoted(line)?,
+            Some('"') => self.double_quoted(line)?,
+            _ => {
+                let raw = self.take_while(|c| c != '\n');
+                strip_comment(&raw).trim_end().to_string()
+            }
+        };
+
+        // Only a comment may follow a closing quote
+        let rest = self.take_while(|c| c != '\n');
+        let rest = rest.trim_start();
+        if !rest.is_empty() && !rest.starts_with('#') {
+            return Err(self.error(line, &format!("unexpected {:?} after value", rest)));
+        }
+
+        Ok((key, value))
+    }
+
+    fn single_quoted(&mut self, line: usize) -> Result<String, SwarmNodeError> {
+        self.next_char();
+        let mut value = String::new();
+        loop {
+            match self.next_char() {
+                Some('\'') => return Ok(value),
+                Some(c) => value.push(c),
+                None => return Err(self.error(line, "unterminated single-quoted value")),
+            }
+        }
+    }
+
+    fn double_quoted(&mut self, line: usize) -> Result<String, SwarmNodeError> {
+        self.next_char();
+        let mut value = String::new();
+        loop {
+            match self.next_char() {
+                Some('"') => return Ok(value),
+                Some('\\') => match self.next_char() {
+                    Some('n') => value.push('\n'),
+                    Some('r') => value.push('\r'),
+                    Some('t') => value.push('\t'),
+                    Some(c @ ('"' | '\\' | '$')) => value.push(c),
+                    Some(c) => {
+                        value.push('\\');
+                        value.push(c);
+                    }
+                    None => break,
+                },
+                Some(c) => value.push(c),
+                None => break,
+            }
+        }
+        Err(self.error(line, "unterminated double-quoted value"))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn take_while(&mut self, keep: impl Fn(char) -> bool) -> String {
+        let mut taken = String::new();
+        while let Some(c) = self.peek() {
+            if !keep(c) {
+                break;
+            }
+            taken.push(c);
+            self.next_char();
+        }
+        taken
+    }
+
+    fn skip_while(&mut self, skip: impl Fn(char) -> bool) {
+        self.take_while(skip);
+    }
+
+    fn error(&self, line: usize, message: &str) -> SwarmNodeError {
+        SwarmNodeError::InvalidArgument(format!("Invalid .env content, line {}: {}", line, message))
+    }
+}
+
+// `export` followed by spaces or tabs is shell syntax, not part of the key
+fn strip_export(key: &str) -> &str {
+    match key.strip_prefix("export") {
+        Some(rest) if rest.starts_with([' ', '\t']) => rest.trim_start(),
+        _ => key,
+    }
+}
+
+// Unquoted values end at a `#` that follows whitespace inside the value, so
+// `KEY=#abc` keeps its `#`
+fn strip_comment(value: &str) -> &str {
+    let mut previous = None;
+    for (i, c) in value.char_indices() {
+        if c == '#' && previous.is_some_and(char::is_whitespace) {
+            return &value[..i];
+        }
+        previous = Some(c);
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> EnvVars {
+        EnvVars::parse(input).unwrap()
+    }
+
+    fn read(json: &str) -> EnvVars {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn parses_quoted_values() {
+        let vars = parse("A='it is $HOME \\n'\nB=\"say \\\"hi\\\"\"\nC=  plain value  \n");
+        assert_eq!(vars.get("A"), Some("it is $HOME \\n"));
+        assert_eq!(vars.get("B"), Some("say \"hi\""));
+        assert_eq!(vars.get("C"), Some("plain value"));
+    }
+
+    #[test]
+    fn quoted_values_span_lines() {
+        let vars = parse("KEY=\"-----BEGIN-----\nabc\n-----END-----\"\nB='x\ny'\nC=1\n");
+        assert_eq!(vars.get("KEY"), Some("-----BEGIN-----\nabc\n-----END-----"));
+        assert_eq!(vars.get("B"), Some("x\ny"));
+        assert_eq!(vars.get("C"), Some("1"));
+    }
+
+    #[test]
+    fn double_quoted_escapes() {
+        let vars = parse(r#"A="a\nb\tc\rd\\e\$f\qg""#);
+        assert_eq!(vars.get("A"), Some("a\nb\tc\rd\\e$f\\qg"));
+    }
+
+    #[test]
+    fn comments() {
+        let vars =
+            parse("# header\nA=1 # trailing\nB='x' # after quote\nC=#abc\nD=a#b\n  # indented\n");
+        assert_eq!(vars.get("A"), Some("1"));
+        assert_eq!(vars.get("B"), Some("x"));
+        assert_eq!(vars.get("C"), Some("#abc"));
+        assert_eq!(vars.get("D"), Some("a#b"));
+        assert_eq!(vars.len(), 4);
+    }
+
+    #[test]
+    fn export_prefix() {
+        let vars = parse("export A=1\nexport\tB=2\nexportC=3\n");
+        assert_eq!(vars.get("A"), Some("1"));
+        assert_eq!(vars.get("B"), Some("2"));
+        assert_eq!(vars.get("exportC"), Some("3"));
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let vars = parse("A=1\r\nB=\"two\"\r\n# comment\r\nC='3' # c\r\n");
+        assert_eq!(vars.get("A"), Some("1"));
+        assert_eq!(vars.get("B"), Some("two"));
+        assert_eq!(vars.get("C"), Some("3"));
+        assert_eq!(vars.len(), 3);
+    }
+
+    #[test]
+    fn strict_parse_reports_the_line() {
+        let error = EnvVars::parse("A=1\nB\n").unwrap_err().to_string();
+        assert!(error.contains("line 2"), "{}", error);
+        let error = EnvVars::parse("A=1\n\nFOO.BAR=1\n")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("line 3"), "{}", error);
+        assert!(EnvVars::parse("A=\"open\nB=1\n").is_err());
+        assert!(EnvVars::parse("A='x' y\n").is_err());
+        assert!(EnvVars::new().insert("1A", "x").is_err());
+    }
+
+    #[test]
+    fn env_string_round_trips() {
+        let mut vars = EnvVars::new();
+        for (key, value) in [
+            ("PLAIN", "value"),
+            ("EMPTY", ""),
+            ("SPACES", "  padded  "),
+            ("HASH", "#abc"),
+            ("QUOTES", "it's \"quoted\""),
+            ("ESCAPES", "back\\slash\ttab\r\nnewline"),
+            ("DOLLAR", "$HOME"),
+            ("UNICODE", "héllo ✓"),
+        ] {
+            vars.insert(key, value).unwrap();
+        }
+        assert_eq!(parse(&vars.to_env_string()), vars);
+    }
+
+    #[test]
+    fn debug_and_display_redact_values() {
+        let vars = parse("A=secret\nB=hunter2\n");
+        assert_eq!(format!("{:?}", vars), r#"{"A": "***", "B": "***"}"#);
+        assert_eq!(vars.to_string(), "A=***, B=***");
+
+        let vars = read(r#""A=secret\nFOO.BAR=hunter2""#);
+        assert_eq!(format!("{:?}", vars), r#"{"A": "***", "<unparsed>": 1}"#);
+        assert_eq!(vars.to_string(), "A=***, <1 unparsed>");
+    }
+
+    #[test]
+    fn reading_keeps_what_it_cannot_parse() {
+        let vars = read(r#""FOO.BAR=1""#);
+        assert!(vars.is_empty());
+        assert_eq!(vars.unparsed().collect::<Vec<_>>(), ["FOO.BAR=1"]);
+
+        let vars = read(r#""A=1\nnot a variable\r\nB=\"open\nexport\tC=3""#);
+        assert_eq!(vars.get("A"), Some("1"));
+        assert_eq!(vars.get("C"), Some("3"));
+        assert_eq!(
+            vars.unparsed().collect::<Vec<_>>(),
+            ["not a variable", "B=\"open"]
+        );
+        assert_eq!(vars.to_env_string(), "A=1\nC=3\nnot a variable\nB=\"open\n");
+    }
+
+    #[test]
+    fn reading_a_map_keeps_invalid_keys() {
+        let vars = read(r#"{"A": "1", "FOO.BAR": "x y"}"#);
+        assert_eq!(vars.get("A"), Some("1"));
+        assert_eq!(vars.unparsed().collect::<Vec<_>>(), ["FOO.BAR=x y"]);
+        assert_eq!(
+            serde_json::to_string(&vars).unwrap(),
+            r#""A=1\nFOO.BAR=x y\n""#
+        );
+    }
+}
@@ -0,0 +1,392 @@
+use reqwest::header::HeaderMap;
+use reqwest::{Response, StatusCode};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use tokio_tungstenite::tungstenite;
+
+use crate::resources::build::Build;
+use crate::resources::execution::Execution;
+
+// Headers the API (or the proxy in front of it) uses to identify a request
+const REQUEST_ID_HEADERS: [&str; 2] = ["x-request-id", "x-amzn-requestid"];
+
+// Details of a non-2xx response returned by the API
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+    pub field_errors: HashMap<String, Vec<String>>,
+    pub request_id: Option<String>,
+    pub body: Option<Value>,
+}
+
+impl ApiError {
+    // Build the error from the status, headers and raw body of a response.
+    // Validation failures come back as `{"field": ["message", ...]}` or, when
+    // not tied to a field, `["message", ...]`, and other failures as
+    // `{"detail": "message"}`.
+    pub fn new(status: StatusCode, headers: &HeaderMap, body: &[u8]) -> Self {
+        let request_id = REQUEST_ID_HEADERS.iter().find_map(|name| {
+            headers
+                .get(*name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        });
+
+        let body: Option<Value> = serde_json::from_slice(body).ok();
+        let mut message = None;
+        let mut field_errors = HashMap::new();
+
+        match &body {
+            Some(Value::Object(fields)) => {
+                for (key, value) in fields {
+                    let messages = messages_of(value);
+                    if key == "detail" || key == "message" {
+                        message = messages.into_iter().next();
+                    } else {
+                        field_errors.insert(key.clone(), messages);
+                    }
+                }
+            }
+            Some(value @ Value::Array(_)) => message = Some(messages_of(value).join(", ")),
+            _ => {}
+        }
+
+        let message = message.unwrap_or_else(|| {
+            status
+                .canonical_reason()
+                .unwrap_or("Unknown status")
+                .to_string()
+        });
+
+        ApiError {
+            status,
+            message,
+            field_errors,
+            request_id,
+            body,
+        }
+    }
+}
+
+fn messages_of(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(s) => vec![s.clone()],
+        Value::Array(items) => items
+            .iter()
+            .map(|item| match item {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            })
+            .collect(),
+        other => vec![other.to_string()],
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HTTP {}: {}", self.status.as_u16(), self.message)?;
+        let mut fields: Vec<_> = self.field_errors.iter().collect();
+        fields.sort_by(|a, b| a.0.cmp(b.0));
+        for (field, messages) in fields {
+            write!(f, "; {}: {}", field, messages.join(", "))?;
+        }
+        if let Some(request_id) = &self.request_id {
+            write!(f, " (request id: {})", request_id)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum SwarmNodeError {
+    BadRequest(Box<ApiError>),
+    Unauthenticated(Box<ApiError>),
+    NotFound(Box<ApiError>),
+    Api(Box<ApiError>),
+    ApiKeyNotSet,
+    Auth(String),
+    Config(String),
+    // An argument was rejected before any request was sent
+    InvalidArgument(String),
+    Http(reqwest::Error),
+    WebSocket(Box<tungstenite::Error>),
+    Decode(String),
+    // An execution ended with a status other than success
+    ExecutionFailed(Box<Execution>),
+    // A build ended in failure, its logs say why
+    BuildFailed(Box<Build>),
+    // A wait for something to finish ran out of time
+    Timeout(String),
+    // A non-idempotent call failed in a way that leaves it unclear whether
+    // the server acted on it. Repeating it with the same idempotency key is
+    // safe; repeating it without one may create a duplicate.
+    OutcomeUnknown {
+        idempotency_key: Option<String>,
+        source: Box<SwarmNodeError>,
+    },
+    Other(String),
+}
+
+impl fmt::Display for SwarmNodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SwarmNodeError::BadRequest(ref err) => write!(f, "Bad Request: {}", err),
+            SwarmNodeError::Unauthenticated(ref err) => write!(f, "Unauthenticated: {}", err),
+            SwarmNodeError::NotFound(ref err) => write!(f, "Not Found: {}", err),
+            SwarmNodeError::Api(ref err) => write!(f, "API Error: {}", err),
+            SwarmNodeError::ApiKeyNotSet => write!(f, "API Key not set"),
+            SwarmNodeError::Auth(ref msg) => write!(f, "Failed to load credentials: {}", msg),
+            SwarmNodeError::Config(ref msg) => write!(f, "Invalid configuration: {}", msg),
+            SwarmNodeError::InvalidArgument(ref msg) => write!(f, "Invalid argument: {}", msg),
+            SwarmNodeError::Http(ref err) => write!(f, "Request failed: {}", err),
+            SwarmNodeError::WebSocket(ref err) => write!(f, "WebSocket error: {}", err),
+            SwarmNodeError::Decode(ref msg) => write!(f, "Failed to decode response: {}", msg),
+            SwarmNodeError::ExecutionFailed(ref execution) => write!(
+                f,
+                "Execution {} ended with status {}",
+                execution.id, execution.status
+            ),
+            SwarmNodeError::BuildFailed(ref build) => {
+                write!(f, "Build {} of agent {} failed", build.id, build.agent_id)
+            }
+            SwarmNodeError::Timeout(ref msg) => write!(f, "Timed out: {}", msg),
+            SwarmNodeError::OutcomeUnknown {
+                ref idempotency_key,
+                ref source,
+            } => match idempotency_key {
+                Some(key) => write!(f, "Outcome unknown (idempotency key {}): {}", key, source),
+                None => write!(f, "Outcome unknown: {}", source),
+            },
+            SwarmNodeError::Other(ref msg) => write!(f, "Other Error: {}", msg),
+        }
+    }
+}
+
+impl Error for SwarmNodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SwarmNodeError::Http(err) => Some(err),
+            SwarmNodeError::WebSocket(err) => Some(err.as_ref()),
+            SwarmNodeError::OutcomeUnknown { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for SwarmNodeError {
+    fn from(err: reqwest::Error) -> Self {
+        SwarmNodeError::Http(err)
+    }
+}
+
+impl From<tungstenite::Error> for SwarmNodeError {
+    fn from(err: tungstenite::Error) -> Self {
+        match err {
+            // The handshake was answered with a regular HTTP error
+            tungstenite::Error::Http(response) => {
+                let body = response.body().as_deref().unwrap_or_default();
+                ApiError::new(response.status(), response.headers(), body).into()
+            }
+            err => SwarmNodeError::WebSocket(Box::new(err)),
+        }
+    }
+}
+
+impl From<ApiError> for SwarmNodeError {
+    fn from(err: ApiError) -> Self {
+        let err = Box::new(err);
+        match err.status {
+            StatusCode::BAD_REQUEST => SwarmNodeError::BadRequest(err),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                SwarmNodeError::Unauthenticated(err)
+            }
+            StatusCode::NOT_FOUND => SwarmNodeError::NotFound(err),
+            _ => SwarmNodeError::Api(err),
+        }
+    }
+}
+
+impl SwarmNodeError {
+    // Consume a non-2xx response and turn it into a typed error
+    pub async fn from_response(response: Response) -> Self {
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.bytes().await.unwrap_or_default();
+
+        ApiError::new(status, &headers, &body).into()
+    }
+
+    pub fn api_error(&self) -> Option<&ApiError> {
+        match self {
+            SwarmNodeError::OutcomeUnknown { source, .. } => source.api_error(),
+            SwarmNodeError::BadRequest(err)
+            | SwarmNodeError::Unauthenticated(err)
+            | SwarmNodeError::NotFound(err)
+            | SwarmNodeError::Api(err) => Some(err),
+            _ => None,
+        }
+    }
+
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            SwarmNodeError::Http(err) => err.status(),
+            SwarmNodeError::OutcomeUnknown { source, .. } => source.status(),
+            _ => self.api_error().map(|err| err.status),
+        }
+    }
+
+    pub fn request_id(&self) -> Option<&str> {
+        self.api_error().and_then(|err| err.request_id.as_deref())
+    }
+
+    pub fn field_errors(&self) -> Option<&HashMap<String, Vec<String>>> {
+        self.api_error().map(|err| &err.field_errors)
+    }
+
+    pub fn is_outcome_unknown(&self) -> bool {
+        matches!(self, SwarmNodeError::OutcomeUnknown { .. })
+    }
+
+    // Whether the server may have acted on the request even though it
+    // failed: the connection broke after the request was sent, or a gateway
+    // gave up waiting for the backend
+    pub fn may_have_been_processed(&self) -> bool {
+        match self {
+            SwarmNodeError::Http(err) => !err.is_connect() && !err.is_builder(),
+            _ => matches!(
+                self.status(),
+                Some(StatusCode::INTERNAL_SERVER_ERROR)
+                    | Some(StatusCode::BAD_GATEWAY)
+                    | Some(StatusCode::GATEWAY_TIMEOUT)
+            ),
+        }
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, SwarmNodeError::NotFound(_))
+    }
+
+    // Whether sending the same request again may succeed: throttling, server
+    // side failures and transport errors that never produced a response
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            SwarmNodeError::Http(err) => err.is_connect() || err.is_timeout() || err.is_request(),
+            SwarmNodeError::OutcomeUnknown { source, .. } => source.is_retryable(),
+            SwarmNodeError::WebSocket(err) => matches!(
+                **err,
+                tungstenite::Error::Io(_) | tungstenite::Error::ConnectionClosed
+            ),
+            SwarmNodeError::Api(err) => matches!(
+                err.status,
+                StatusCode::REQUEST_TIMEOUT
+                    | StatusCode::TOO_MANY_REQUESTS
+                    | StatusCode::INTERNAL_SERVER_ERROR
+                    | StatusCode::BAD_GATEWAY
+                    | StatusCode::SERVICE_UNAVAILABLE
+                    | StatusCode::GATEWAY_TIMEOUT
+            ),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn api_error(status: StatusCode, body: &str) -> ApiError {
+        ApiError::new(status, &HeaderMap::new(), body.as_bytes())
+    }
+
+    #[test]
+    fn detail_body_becomes_the_message() {
+        let err = api_error(StatusCode::NOT_FOUND, r#"{"detail": "Not found."}"#);
+        assert_eq!(err.message, "Not found.");
+        assert!(err.field_errors.is_empty());
+        assert_eq!(err.to_string(), "HTTP 404: Not found.");
+    }
+
+    #[test]
+    fn validation_body_becomes_field_errors() {
+        let err = api_error(
+            StatusCode::BAD_REQUEST,
+            r#"{"name": ["This field is required.", "Too short."], "script": "Invalid."}"#,
+        );
+        assert_eq!(err.message, "Bad Request");
+        assert_eq!(
+            err.field_errors["name"],
+            vec!["This field is required.", "Too short."]
+        );
+        assert_eq!(err.field_errors["script"], vec!["Invalid."]);
+        assert_eq!(
+            err.to_string(),
+            "HTTP 400: Bad Request; name: This field is required., Too short.; script: Invalid."
+        );
+    }
+
+    #[test]
+    fn nested_field_errors_are_kept_as_json() {
+        let err = api_error(
+            StatusCode::BAD_REQUEST,
+            r#"{"env_vars": {"FOO": ["Invalid."]}, "count": [3]}"#,
+        );
+        assert_eq!(
+            err.field_errors["env_vars"],
+            vec![r#"{"FOO":["Invalid."]}"#]
+        );
+        assert_eq!(err.field_errors["count"], vec!["3"]);
+    }
+
+    #[test]
+    fn list_body_becomes_the_message() {
+        let err = api_error(
+            StatusCode::BAD_REQUEST,
+            r#"["Agent is not built.", "Retry."]"#,
+        );
+        assert_eq!(err.message, "Agent is not built., Retry.");
+        assert!(err.field_errors.is_empty());
+    }
+
+    #[test]
+    fn non_json_body_falls_back_to_the_status_reason() {
+        let err = api_error(StatusCode::BAD_GATEWAY, "<html>Bad gateway</html>");
+        assert_eq!(err.message, "Bad Gateway");
+        assert!(err.body.is_none());
+
+        let err = api_error(StatusCode::from_u16(599).unwrap(), "");
+        assert_eq!(err.message, "Unknown status");
+    }
+
+    #[test]
+    fn request_id_is_read_from_either_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-amzn-requestid", HeaderValue::from_static("abc"));
+        let err = ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, &headers, b"");
+        assert_eq!(err.request_id.as_deref(), Some("abc"));
+
+        headers.insert("x-request-id", HeaderValue::from_static("def"));
+        let err = ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, &headers, b"");
+        assert_eq!(err.request_id.as_deref(), Some("def"));
+        assert!(err.to_string().ends_with("(request id: def)"));
+    }
+
+    #[test]
+    fn status_picks_the_error_variant() {
+        let err = |status| SwarmNodeError::from(api_error(status, ""));
+        assert!(matches!(
+            err(StatusCode::BAD_REQUEST),
+            SwarmNodeError::BadRequest(_)
+        ));
+        assert!(matches!(
+            err(StatusCode::FORBIDDEN),
+            SwarmNodeError::Unauthenticated(_)
+        ));
+        assert!(err(StatusCode::NOT_FOUND).is_not_found());
+        assert!(err(StatusCode::TOO_MANY_REQUESTS).is_retryable());
+        assert!(!err(StatusCode::CONFLICT).is_retryable());
+    }
+}
@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use super::error::SwarmNodeError;
+
+// Newtype over the string id of one kind of resource, so that ids of
+// different resources cannot be mixed up. Ids are parsed with `str::parse`
+// and serialize as plain strings.
+macro_rules! resource_id {
+    ($name:ident, $resource:literal) => {
+        #[doc = concat!("Id of ", $resource)]
+        #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+        #[serde(transparent)]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        // Ids end up in URL paths, so only letters, digits, `-` and `_` are
+        // accepted: `..`, `/`, `?` or `#` would send the request elsewhere
+        impl FromStr for $name {
+            type Err = SwarmNodeError;
+
+            fn from_str(id: &str) -> Result<Self, Self::Err> {
+                if !is_valid_id(id) {
+                    return Err(SwarmNodeError::InvalidArgument(format!(
+                        "{:?} is not a valid id of {}",
+                        id, $resource
+                    )));
+                }
+                Ok($name(id.to_string()))
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(id: $name) -> String {
+                id.0
+            }
+        }
+    };
+}
+
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+resource_id!(AgentId, "an agent");
+resource_id!(StoreId, "a store");
+resource_id!(ExecutionId, "an execution");
+resource_id!(BuildId, "a build");
+resource_id!(BuilderJobId, "an agent builder job");
+resource_id!(ExecutorJobId, "an agent executor job");
+resource_id!(CronJobId, "an agent executor cron job");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_parse_and_display_as_is() {
+        let id: AgentId = "3f2b1c9e-7a4d-4e0b-9c1a-2d5e6f7a8b9c".parse().unwrap();
+        assert_eq!(id.as_str(), "3f2b1c9e-7a4d-4e0b-9c1a-2d5e6f7a8b9c");
+        assert_eq!(id.to_string(), id.as_str());
+        assert!("agent_01".parse::<StoreId>().is_ok());
+    }
+
+    #[test]
+    fn ids_that_would_change_the_url_are_rejected() {
+        for id in ["", "..", ".", "a/b", "a?b=c", "a#b", "a b", "a%2F", "é"] {
+            let error = id.parse::<AgentId>().unwrap_err();
+            assert!(
+                matches!(error, SwarmNodeError::InvalidArgument(_)),
+                "{:?}: {:?}",
+                id,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn ids_serialize_as_plain_strings() {
+        let id: BuildId = "b-1".parse().unwrap();
+        assert_eq!(serde_json::to_string(&id).unwrap(), r#""b-1""#);
+        assert_eq!(serde_json::from_str::<BuildId>(r#""b-1""#).unwrap(), id);
+        assert_eq!(
+            serde_json::from_str::<Vec<CronJobId>>(r#"["x", "y"]"#)
+                .unwrap()
+                .iter()
+                .map(CronJobId::as_str)
+                .collect::<Vec<_>>(),
+            ["x", "y"]
+        );
+    }
+}
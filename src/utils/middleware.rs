@@ -0,0 +1,92 @@
+use reqwest::header::HeaderMap;
+use reqwest::{Method, StatusCode, Url};
+use serde::Serialize;
+use std::time::Duration;
+
+use super::error::SwarmNodeError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestKind {
+    Rest,
+    WebSocket,
+}
+
+// A REST request or WebSocket handshake about to be sent. Headers can be
+// modified; the `Authorization` header is already set.
+pub struct OutgoingRequest<'a> {
+    pub kind: RequestKind,
+    pub method: &'a Method,
+    pub url: &'a Url,
+    pub headers: &'a mut HeaderMap,
+    // 1 for the first attempt, incremented on every retry
+    pub attempt: u32,
+}
+
+// Outcome of a request, reported once per attempt. `status` and `headers`
+// are unset when no response was received.
+pub struct IncomingResponse<'a> {
+    pub kind: RequestKind,
+    pub method: &'a Method,
+    pub url: &'a Url,
+    pub attempt: u32,
+    pub status: Option<StatusCode>,
+    pub headers: Option<&'a HeaderMap>,
+    pub error: Option<&'a SwarmNodeError>,
+    pub elapsed: Duration,
+    // Whether the response was produced by a middleware instead of the API
+    pub synthetic: bool,
+}
+
+// Response returned by a middleware in place of calling the API
+#[derive(Debug, Clone)]
+pub struct SyntheticResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl SyntheticResponse {
+    pub fn new(status: StatusCode, body: impl Into<Vec<u8>>) -> Self {
+        SyntheticResponse {
+            status,
+            headers: HeaderMap::new(),
+            body: body.into(),
+        }
+    }
+
+    pub fn json<T: Serialize + ?Sized>(
+        status: StatusCode,
+        body: &T,
+    ) -> Result<Self, SwarmNodeError> {
+        let body = serde_json::to_vec(body).map_err(|e| SwarmNodeError::Other(e.to_string()))?;
+        let mut response = SyntheticResponse::new(status, body);
+        response.headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            reqwest::header::HeaderValue::from_static("application/json"),
+        );
+        Ok(response)
+    }
+
+    pub(crate) fn into_response(self) -> reqwest::Response {
+        let mut response = http::Response::new(self.body);
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers;
+        reqwest::Response::from(response)
+    }
+}
+
+// Hook around every call made by a client. Middlewares run in the order
+// they were configured on the way out and in reverse order on the way back.
+// Returning a response from `on_request` skips the remaining middlewares and
+// the network call; for WebSocket handshakes it fails the connection with
+// that response.
+pub trait Middleware: Send + Sync {
+    fn on_request(&self, request: &mut OutgoingRequest<'_>) -> Option<SyntheticResponse> {
+        let _ = request;
+        None
+    }
+
+    fn on_response(&self, response: &IncomingResponse<'_>) {
+        let _ = response;
+    }
+}
@@ -1,62 +1,189 @@
-use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use async_stream::try_stream;
+use futures_util::{stream, Stream, StreamExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::pin::pin;
 
-use super::client::SwarmClient as Client;
+use reqwest::Url;
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+use super::client::{RequestOptions, SwarmNodeClient};
+use super::error::SwarmNodeError;
+
+// Page body shared by the cursor and page-number paginated endpoints
+#[derive(Deserialize)]
+struct Page<T> {
+    next: Option<String>,
+    previous: Option<String>,
+    results: Vec<T>,
+    #[serde(default)]
+    total_count: u32,
+    #[serde(default)]
+    current_page: u32,
+}
+
+async fn fetch_page<T: DeserializeOwned>(
+    client: &SwarmNodeClient,
+    url: &str,
+) -> Result<Page<T>, SwarmNodeError> {
+    let response = client
+        .request_url("GET", url, None::<&Value>, &RequestOptions::default())
+        .await?;
+    let body = response.bytes().await?;
+    serde_json::from_slice(&body).map_err(|e| SwarmNodeError::Decode(e.to_string()))
+}
+
+const DEFAULT_PAGE_SIZE: u32 = 10;
+
+// `page_size` query parameter of a list call. It must be positive and at
+// most `max_page_size`, the client's `SwarmNodeConfig::max_page_size`.
+pub(crate) fn page_size_param(
+    page_size: Option<u32>,
+    max_page_size: Option<u32>,
+) -> Result<String, SwarmNodeError> {
+    let page_size = page_size.unwrap_or(DEFAULT_PAGE_SIZE);
+    if page_size == 0 {
+        return Err(SwarmNodeError::InvalidArgument(
+            "page_size must be at least 1".to_string(),
+        ));
+    }
+    if let Some(max_page_size) = max_page_size.filter(|max| page_size > *max) {
+        return Err(SwarmNodeError::InvalidArgument(format!(
+            "page_size must be at most {}, got {}",
+            max_page_size, page_size
+        )));
+    }
+    Ok(page_size.to_string())
+}
+
+// Query parameters of a list call that select what is listed, as opposed to
+// which page of it
+pub(crate) fn filters_of(params: &HashMap<String, String>) -> BTreeMap<String, String> {
+    params
+        .iter()
+        .filter(|(key, _)| key.as_str() != "page")
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+// Saved position in a list scan. It serializes with serde, so it can be
+// written to disk or a database and handed to `resume` after a restart to
+// fetch the page it points at, with the filters of the original call.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PageToken {
+    pub resource_class: String,
+    pub url: String,
+    pub filters: BTreeMap<String, String>,
+}
+
+impl PageToken {
+    // URL of the page, with the filters the API left out of it added back
+    fn page_url(&self) -> Result<Url, SwarmNodeError> {
+        let mut url = Url::parse(&self.url).map_err(|e| {
+            SwarmNodeError::Decode(format!("Invalid page token URL {}: {}", self.url, e))
+        })?;
+
+        let missing: Vec<_> = self
+            .filters
+            .iter()
+            .filter(|(key, _)| !url.query_pairs().any(|(k, _)| k == key.as_str()))
+            .collect();
+        if !missing.is_empty() {
+            url.query_pairs_mut().extend_pairs(missing);
+        }
+        Ok(url)
+    }
+}
+
+// A page bound to the client that fetched it. It is not `Deserialize`: a
+// page read back from storage would have no client to fetch its neighbours
+// with. Save a `PageToken` and `resume` it with the right client instead.
+#[derive(Debug, Serialize, Clone)]
 pub struct CursorPaginatedResource<T> {
     pub next_url: Option<String>,
     pub previous_url: Option<String>,
-    pub resource_class: &'static str,
+    pub resource_class: String,
+    // Filters of the list call this page came from
+    pub filters: BTreeMap<String, String>,
     pub results: Vec<T>,
+    #[serde(skip)]
+    pub(crate) client: SwarmNodeClient,
 }
 
 impl<T> CursorPaginatedResource<T>
 where
     T: DeserializeOwned + std::fmt::Debug,
 {
-    pub async fn next(&self) -> Option<Self> {
-        if self.next_url.is_none() {
-            return None;
+    // Following page, or `None` when this is the last one
+    pub async fn next(&self) -> Result<Option<Self>, SwarmNodeError> {
+        match &self.next_url {
+            Some(url) => self.fetch(url).await.map(Some),
+            None => Ok(None),
         }
+    }
 
-        let url = self.next_url.as_ref().unwrap();
-        let response = Client::request_url("GET", url, None).await.unwrap(); // handle error properly
+    // Preceding page, or `None` when this is the first one
+    pub async fn previous(&self) -> Result<Option<Self>, SwarmNodeError> {
+        match &self.previous_url {
+            Some(url) => self.fetch(url).await.map(Some),
+            None => Ok(None),
+        }
+    }
 
-        let json: Value = response.json().await.unwrap(); // handle error properly
-        let next_url = json["next"].as_str().map(|s| s.to_string());
-        let previous_url = json["previous"].as_str().map(|s| s.to_string());
+    // Token of the following page, `None` when this is the last one
+    pub fn next_token(&self) -> Option<PageToken> {
+        self.next_url.as_deref().map(|url| self.token(url))
+    }
 
-        let results: Vec<T> = serde_json::from_value(json["results"].clone()).unwrap(); // handle error properly
+    // Token of the preceding page, `None` when this is the first one
+    pub fn previous_token(&self) -> Option<PageToken> {
+        self.previous_url.as_deref().map(|url| self.token(url))
+    }
 
-        Some(CursorPaginatedResource {
-            next_url,
-            previous_url: previous_url,
-            resource_class: self.resource_class,
-            results,
+    // Fetch the page a token saved earlier points at
+    pub async fn resume(
+        client: &SwarmNodeClient,
+        token: &PageToken,
+    ) -> Result<Self, SwarmNodeError> {
+        let page = fetch_page(client, token.page_url()?.as_str()).await?;
+        Ok(CursorPaginatedResource {
+            next_url: page.next,
+            previous_url: page.previous,
+            resource_class: token.resource_class.clone(),
+            filters: token.filters.clone(),
+            results: page.results,
+            client: client.clone(),
         })
     }
 
-    pub async fn previous(&self) -> Option<Self> {
-        if self.previous_url.is_none() {
-            return None;
+    fn token(&self, url: &str) -> PageToken {
+        PageToken {
+            resource_class: self.resource_class.clone(),
+            url: url.to_string(),
+            filters: self.filters.clone(),
         }
+    }
 
-        let url = self.previous_url.as_ref().unwrap();
-        let response = Client::request_url("GET", url, None).await.unwrap(); // handle error properly
-
-        let json: Value = response.json().await.unwrap(); // handle error properly
-        let next_url = json["next"].as_str().map(|s| s.to_string());
-        let previous_url = json["previous"].as_str().map(|s| s.to_string());
-
-        let results: Vec<T> = serde_json::from_value(json["results"].clone()).unwrap(); // handle error properly
+    async fn fetch(&self, url: &str) -> Result<Self, SwarmNodeError> {
+        Self::resume(&self.client, &self.token(url)).await
+    }
 
-        Some(CursorPaginatedResource {
-            next_url,
-            previous_url: previous_url,
-            resource_class: self.resource_class,
-            results,
-        })
+    // Items of this page followed by those of every following page. Pages
+    // are fetched lazily as the stream is polled, so `take` or dropping the
+    // stream stops further requests.
+    pub fn into_stream(self) -> impl Stream<Item = Result<T, SwarmNodeError>> {
+        try_stream! {
+            let mut page = self;
+            loop {
+                for item in std::mem::take(&mut page.results) {
+                    yield item;
+                }
+                match page.next_url.take() {
+                    Some(url) => page = page.fetch(&url).await?,
+                    None => break,
+                }
+            }
+        }
     }
 }
 
@@ -71,72 +198,212 @@ impl<T: std::fmt::Debug> std::fmt::Display for CursorPaginatedResource<T> {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+// A page bound to the client that fetched it, see `CursorPaginatedResource`
+#[derive(Debug, Serialize, Clone)]
 pub struct PagePaginatedResource<T> {
     pub next_url: Option<String>,
     pub previous_url: Option<String>,
-    pub resource_class: &'static str, // Class name as a string
+    pub resource_class: String, // Class name as a string
+    // Filters of the list call this page came from
+    pub filters: BTreeMap<String, String>,
     pub total_count: u32,
     pub current_page: u32,
     pub results: Vec<T>,
+    #[serde(skip)]
+    pub(crate) client: SwarmNodeClient,
 }
 
 impl<T> PagePaginatedResource<T>
 where
     T: DeserializeOwned + std::fmt::Debug,
 {
-    pub async fn next(&self) -> Option<Self> {
-        if self.next_url.is_none() {
-            return None;
+    // Following page, or `None` when this is the last one
+    pub async fn next(&self) -> Result<Option<Self>, SwarmNodeError> {
+        match &self.next_url {
+            Some(url) => self.fetch(url).await.map(Some),
+            None => Ok(None),
         }
+    }
 
-        let url = self.next_url.as_ref().unwrap();
-        let response = Client::request_url("GET", url, None).await.unwrap(); // handle error properly
-
-        let json: Value = response.json().await.unwrap(); // handle error properly
-        let next_url = json["next"].as_str().map(|s| s.to_string());
-        let previous_url = json["previous"].as_str().map(|s| s.to_string());
+    // Preceding page, or `None` when this is the first one
+    pub async fn previous(&self) -> Result<Option<Self>, SwarmNodeError> {
+        match &self.previous_url {
+            Some(url) => self.fetch(url).await.map(Some),
+            None => Ok(None),
+        }
+    }
 
-        let total_count = json["total_count"].as_u64().unwrap_or(0) as u32;
-        let current_page = json["current_page"].as_u64().unwrap_or(0) as u32;
+    // Token of the following page, `None` when this is the last one
+    pub fn next_token(&self) -> Option<PageToken> {
+        self.next_url.as_deref().map(|url| self.token(url))
+    }
 
-        let results: Vec<T> = serde_json::from_value(json["results"].clone()).unwrap(); // handle error properly
+    // Token of the preceding page, `None` when this is the first one
+    pub fn previous_token(&self) -> Option<PageToken> {
+        self.previous_url.as_deref().map(|url| self.token(url))
+    }
 
-        Some(PagePaginatedResource {
-            next_url: next_url,
-            previous_url: previous_url,
-            resource_class: self.resource_class,
-            total_count,
-            current_page,
-            results,
+    // Fetch the page a token saved earlier points at
+    pub async fn resume(
+        client: &SwarmNodeClient,
+        token: &PageToken,
+    ) -> Result<Self, SwarmNodeError> {
+        let page = fetch_page(client, token.page_url()?.as_str()).await?;
+        Ok(PagePaginatedResource {
+            next_url: page.next,
+            previous_url: page.previous,
+            resource_class: token.resource_class.clone(),
+            filters: token.filters.clone(),
+            total_count: page.total_count,
+            current_page: page.current_page,
+            results: page.results,
+            client: client.clone(),
         })
     }
 
-    pub async fn previous(&self) -> Option<Self> {
-        if self.previous_url.is_none() {
-            return None;
+    fn token(&self, url: &str) -> PageToken {
+        PageToken {
+            resource_class: self.resource_class.clone(),
+            url: url.to_string(),
+            filters: self.filters.clone(),
         }
+    }
 
-        let url = self.previous_url.as_ref().unwrap();
-        let response = Client::request_url("GET", url, None).await.unwrap(); // handle error properly
+    async fn fetch(&self, url: &str) -> Result<Self, SwarmNodeError> {
+        Self::resume(&self.client, &self.token(url)).await
+    }
 
-        let json: Value = response.json().await.unwrap(); // handle error properly
-        let next_url = json["next"].as_str().map(|s| s.to_string());
-        let previous_url = json["previous"].as_str().map(|s| s.to_string());
+    // Number of pages of the list, as of this page's `total_count`
+    pub fn total_pages(&self) -> u32 {
+        self.total_count.div_ceil(self.page_size())
+    }
 
-        let total_count = json["total_count"].as_u64().unwrap_or(0) as u32;
-        let current_page = json["current_page"].as_u64().unwrap_or(0) as u32;
+    fn page_size(&self) -> u32 {
+        self.filters
+            .get("page_size")
+            .and_then(|size| size.parse::<u32>().ok())
+            .filter(|size| *size > 0)
+            .unwrap_or(self.results.len().max(1) as u32)
+    }
 
-        let results: Vec<T> = serde_json::from_value(json["results"].clone()).unwrap(); // handle error properly
+    // Items of this page followed by those of every following page. Pages
+    // are fetched lazily as the stream is polled, so `take` or dropping the
+    // stream stops further requests.
+    pub fn into_stream(self) -> impl Stream<Item = Result<T, SwarmNodeError>> {
+        try_stream! {
+            let mut page = self;
+            loop {
+                for item in std::mem::take(&mut page.results) {
+                    yield item;
+                }
+                match page.next_url.take() {
+                    Some(url) => page = page.fetch(&url).await?,
+                    None => break,
+                }
+            }
+        }
+    }
 
-        Some(PagePaginatedResource {
-            next_url: next_url,
-            previous_url: previous_url,
-            resource_class: self.resource_class,
-            total_count,
-            current_page,
-            results,
-        })
+    // Items of this page followed by those of every following page, like
+    // `into_stream`, but with up to `concurrency` of the remaining pages
+    // requested at once. Items keep their order. Each page is its own request
+    // and is retried on its own under the client's `RetryPolicy`, so a
+    // transient failure does not restart the scan.
+    pub fn into_stream_buffered(
+        self,
+        concurrency: usize,
+    ) -> impl Stream<Item = Result<T, SwarmNodeError>> {
+        let Some(urls) = self.remaining_page_urls() else {
+            // Page numbers cannot be worked out, walk `next_url` instead
+            return self.into_stream().right_stream();
+        };
+
+        let client = self.client.clone();
+        let pages = stream::iter(urls)
+            .map(move |url| {
+                let client = client.clone();
+                async move { fetch_page::<T>(&client, url.as_str()).await }
+            })
+            .buffered(concurrency.max(1));
+
+        try_stream! {
+            let mut page = self;
+            for item in std::mem::take(&mut page.results) {
+                yield item;
+            }
+
+            let mut pages = pin!(pages);
+            let mut next_url = None;
+            while let Some(counted) = pages.next().await {
+                let counted = counted?;
+                next_url = counted.next;
+                for item in counted.results {
+                    yield item;
+                }
+            }
+
+            // The list grew after `total_count` was read: follow `next` from
+            // the last counted page like `into_stream` would
+            if let Some(url) = next_url {
+                let rest = page.fetch(&url).await?.into_stream();
+                let mut rest = pin!(rest);
+                while let Some(item) = rest.next().await {
+                    yield item?;
+                }
+            }
+        }
+        .left_stream()
+    }
+
+    // URLs of the pages after this one, built from `next_url` and the page
+    // count implied by `total_count`
+    fn remaining_page_urls(&self) -> Option<Vec<Url>> {
+        let Some(next_url) = &self.next_url else {
+            return Some(Vec::new());
+        };
+        let next_url = Url::parse(next_url).ok()?;
+
+        let urls: Vec<_> = (self.current_page + 1..=self.total_pages())
+            .map(|page| with_page(&next_url, page))
+            .collect();
+        // A `next_url` past the last counted page means `total_count` is stale
+        (!urls.is_empty()).then_some(urls)
+    }
+}
+
+impl<T> PagePaginatedResource<T>
+where
+    T: DeserializeOwned + std::fmt::Debug + Clone,
+{
+    // Page `page` of the same list, counting from 1
+    pub async fn go_to_page(&self, page: u32) -> Result<Self, SwarmNodeError> {
+        let total_pages = self.total_pages().max(1);
+        if page == 0 || page > total_pages {
+            return Err(SwarmNodeError::InvalidArgument(format!(
+                "Page {} is out of range, the list has {} page(s)",
+                page, total_pages
+            )));
+        }
+
+        // Any neighbour's URL carries the query of the list call
+        match self.next_url.as_deref().or(self.previous_url.as_deref()) {
+            Some(url) => {
+                let url = Url::parse(url).map_err(|e| {
+                    SwarmNodeError::Decode(format!("Invalid page URL {}: {}", url, e))
+                })?;
+                self.fetch(with_page(&url, page).as_str()).await
+            }
+            // Without neighbours this is the only page
+            None => Ok(self.clone()),
+        }
+    }
+
+    pub async fn first(&self) -> Result<Self, SwarmNodeError> {
+        self.go_to_page(1).await
+    }
+
+    pub async fn last(&self) -> Result<Self, SwarmNodeError> {
+        self.go_to_page(self.total_pages().max(1)).await
     }
 }
 
@@ -152,3 +419,277 @@ impl<T: std::fmt::Debug> std::fmt::Display for PagePaginatedResource<T> {
         )
     }
 }
+
+// `url` with its `page` query parameter set to `page`
+fn with_page(url: &Url, page: u32) -> Url {
+    let query: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _)| key != "page")
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+
+    let mut url = url.clone();
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(query)
+        .append_pair("page", &page.to_string());
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::middleware::SyntheticResponse;
+    use crate::utils::retry::RetryPolicy;
+    use crate::utils::testing::{client, FakeApi};
+    use futures_util::TryStreamExt;
+    use reqwest::StatusCode;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn filters(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn token(url: &str) -> PageToken {
+        PageToken {
+            resource_class: "Execution".to_string(),
+            url: url.to_string(),
+            filters: filters(&[("agent_id", "a1"), ("status", "success")]),
+        }
+    }
+
+    #[test]
+    fn page_token_survives_a_serde_round_trip() {
+        let token = token("http://api.test/v1/executions/?cursor=abc");
+        let saved = serde_json::to_string(&token).unwrap();
+        assert_eq!(serde_json::from_str::<PageToken>(&saved).unwrap(), token);
+    }
+
+    #[test]
+    fn page_url_adds_back_only_the_missing_filters() {
+        let url = token("http://api.test/v1/executions/?cursor=abc&agent_id=a1")
+            .page_url()
+            .unwrap();
+        let mut query: Vec<_> = url.query_pairs().into_owned().collect();
+        query.sort();
+        assert_eq!(
+            query,
+            [
+                ("agent_id".to_string(), "a1".to_string()),
+                ("cursor".to_string(), "abc".to_string()),
+                ("status".to_string(), "success".to_string()),
+            ]
+        );
+
+        assert!(token("not a url").page_url().is_err());
+    }
+
+    #[tokio::test]
+    async fn resume_fetches_the_saved_page_with_its_filters() {
+        let api = FakeApi::new(|_| {
+            SyntheticResponse::json(
+                StatusCode::OK,
+                &json!({ "next": null, "previous": null, "results": [1, 2] }),
+            )
+            .unwrap()
+        });
+        let client = client(vec![api.clone()], RetryPolicy::none());
+
+        let saved =
+            serde_json::to_string(&token("http://api.test/v1/executions/?cursor=abc")).unwrap();
+        let token: PageToken = serde_json::from_str(&saved).unwrap();
+        let page = CursorPaginatedResource::<u32>::resume(&client, &token)
+            .await
+            .unwrap();
+
+        assert_eq!(page.results, [1, 2]);
+        assert_eq!(page.filters, token.filters);
+        assert_eq!(
+            api.seen()[0].url.as_str(),
+            "http://api.test/v1/executions/?cursor=abc&agent_id=a1&status=success"
+        );
+    }
+
+    // Page-numbered list of the numbers `0..items`, reporting `total_count`
+    // as `reported_total`
+    fn numbers_api(items: u32, reported_total: u32) -> Arc<FakeApi> {
+        FakeApi::new(move |seen| {
+            let param = |name: &str| {
+                seen.url
+                    .query_pairs()
+                    .find(|(key, _)| key == name)
+                    .and_then(|(_, value)| value.parse::<u32>().ok())
+                    .unwrap()
+            };
+            let (page, page_size) = (param("page"), param("page_size"));
+            let start = (page - 1) * page_size;
+            let end = (start + page_size).min(items);
+            let next = (end < items).then(|| with_page(&seen.url, page + 1).to_string());
+            let body = json!({
+                "next": next,
+                "previous": null,
+                "results": (start..end).collect::<Vec<_>>(),
+                "total_count": reported_total,
+                "current_page": page,
+            });
+            SyntheticResponse::json(StatusCode::OK, &body).unwrap()
+        })
+    }
+
+    async fn first_page(api: &Arc<FakeApi>) -> PagePaginatedResource<u32> {
+        let client = client(vec![api.clone()], RetryPolicy::none());
+        let token = PageToken {
+            resource_class: "Build".to_string(),
+            url: "http://api.test/v1/builds/?page=1&page_size=2".to_string(),
+            filters: filters(&[("page_size", "2")]),
+        };
+        PagePaginatedResource::resume(&client, &token)
+            .await
+            .unwrap()
+    }
+
+    async fn collect(page: PagePaginatedResource<u32>, concurrency: usize) -> Vec<u32> {
+        page.into_stream_buffered(concurrency)
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    fn pages_requested(api: &FakeApi) -> Vec<u32> {
+        api.seen()
+            .iter()
+            .filter_map(|seen| {
+                seen.url
+                    .query_pairs()
+                    .find(|(key, _)| key == "page")
+                    .map(|(_, page)| page.parse().unwrap())
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn buffered_stream_keeps_page_order() {
+        let api = numbers_api(9, 9);
+        let page = first_page(&api).await;
+        assert_eq!(collect(page, 3).await, (0..9).collect::<Vec<_>>());
+        assert_eq!(pages_requested(&api), [1, 2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn buffered_stream_follows_next_when_the_list_grew() {
+        // `total_count` says two pages, but there are four
+        let api = numbers_api(7, 4);
+        let page = first_page(&api).await;
+        assert_eq!(collect(page, 4).await, (0..7).collect::<Vec<_>>());
+        assert_eq!(pages_requested(&api), [1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn buffered_stream_walks_next_when_pages_cannot_be_counted() {
+        // A `total_count` of zero leaves no page to compute
+        let api = numbers_api(5, 0);
+        let page = first_page(&api).await;
+        assert_eq!(collect(page, 2).await, (0..5).collect::<Vec<_>>());
+
+        // A single page needs no further request
+        let api = numbers_api(2, 2);
+        let page = first_page(&api).await;
+        assert_eq!(collect(page, 2).await, [0, 1]);
+        assert_eq!(pages_requested(&api), [1]);
+    }
+
+    fn page(
+        total_count: u32,
+        results: Vec<u32>,
+        filters: BTreeMap<String, String>,
+    ) -> PagePaginatedResource<u32> {
+        PagePaginatedResource {
+            next_url: None,
+            previous_url: None,
+            resource_class: "Build".to_string(),
+            filters,
+            total_count,
+            current_page: 1,
+            results,
+            client: client(Vec::new(), RetryPolicy::none()),
+        }
+    }
+
+    #[test]
+    fn total_pages_uses_the_requested_page_size() {
+        let size_10 = filters(&[("page_size", "10")]);
+        assert_eq!(page(0, vec![], size_10.clone()).total_pages(), 0);
+        assert_eq!(page(10, vec![0; 10], size_10.clone()).total_pages(), 1);
+        assert_eq!(page(11, vec![0; 10], size_10.clone()).total_pages(), 2);
+        // The API may return fewer results than asked for on the last page
+        assert_eq!(page(25, vec![0; 5], size_10).total_pages(), 3);
+    }
+
+    #[test]
+    fn page_size_falls_back_to_the_number_of_results() {
+        assert_eq!(page(9, vec![0; 4], BTreeMap::new()).page_size(), 4);
+        assert_eq!(
+            page(9, vec![0; 4], filters(&[("page_size", "x")])).page_size(),
+            4
+        );
+        assert_eq!(
+            page(9, vec![0; 4], filters(&[("page_size", "0")])).page_size(),
+            4
+        );
+        // An empty page must not make the page count divide by zero
+        assert_eq!(page(0, vec![], BTreeMap::new()).page_size(), 1);
+        assert_eq!(page(9, vec![], BTreeMap::new()).total_pages(), 9);
+    }
+
+    #[test]
+    fn with_page_replaces_only_the_page_parameter() {
+        let url =
+            Url::parse("http://api.test/v1/builds/?page=2&page_size=5&status=success").unwrap();
+        assert_eq!(
+            with_page(&url, 7).as_str(),
+            "http://api.test/v1/builds/?page_size=5&status=success&page=7"
+        );
+
+        let url = Url::parse("http://api.test/v1/builds/").unwrap();
+        assert_eq!(
+            with_page(&url, 1).as_str(),
+            "http://api.test/v1/builds/?page=1"
+        );
+    }
+
+    #[tokio::test]
+    async fn go_to_page_checks_the_range() {
+        let single = page(3, vec![0, 1, 2], filters(&[("page_size", "10")]));
+        for out_of_range in [0, 2] {
+            let error = single.go_to_page(out_of_range).await.unwrap_err();
+            assert!(
+                matches!(error, SwarmNodeError::InvalidArgument(_)),
+                "{:?}",
+                error
+            );
+        }
+        // Without neighbours the only page is returned as is
+        assert_eq!(single.go_to_page(1).await.unwrap().results, [0, 1, 2]);
+        assert_eq!(single.last().await.unwrap().results, [0, 1, 2]);
+
+        // An empty list still has a first page
+        let empty = page(0, vec![], BTreeMap::new());
+        assert!(empty.first().await.unwrap().results.is_empty());
+    }
+
+    #[tokio::test]
+    async fn go_to_page_fetches_through_a_neighbour_url() {
+        let api = numbers_api(9, 9);
+        let first = first_page(&api).await;
+        assert_eq!(first.total_pages(), 5);
+
+        assert_eq!(first.go_to_page(4).await.unwrap().results, [6, 7]);
+        assert_eq!(first.last().await.unwrap().results, [8]);
+        assert!(first.go_to_page(6).await.is_err());
+        assert_eq!(pages_requested(&api), [1, 4, 5]);
+    }
+}
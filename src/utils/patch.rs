@@ -0,0 +1,127 @@
+use serde::{Serialize, Serializer};
+
+use super::error::SwarmNodeError;
+
+// Change to one field of an update. `Unchanged` fields are left out of the
+// request body (mark them `skip_serializing_if = "Patch::is_unchanged"`),
+// `Clear` ones are sent as `null`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Patch<T> {
+    #[default]
+    Unchanged,
+    Set(T),
+    Clear,
+}
+
+impl<T> Patch<T> {
+    pub fn is_unchanged(&self) -> bool {
+        matches!(self, Patch::Unchanged)
+    }
+
+    // The new value, if the field is being set
+    pub fn as_set(&self) -> Option<&T> {
+        match self {
+            Patch::Set(value) => Some(value),
+            Patch::Unchanged | Patch::Clear => None,
+        }
+    }
+}
+
+impl<T: Serialize> Serialize for Patch<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Patch::Set(value) => value.serialize(serializer),
+            Patch::Unchanged | Patch::Clear => serializer.serialize_none(),
+        }
+    }
+}
+
+// Check a field every create call must carry
+pub(crate) fn required(field: &str, value: Option<&str>) -> Result<(), SwarmNodeError> {
+    match value {
+        Some(value) if !value.trim().is_empty() => Ok(()),
+        Some(_) => Err(SwarmNodeError::InvalidArgument(format!(
+            "{} must not be empty",
+            field
+        ))),
+        None => Err(SwarmNodeError::InvalidArgument(format!(
+            "{} is required",
+            field
+        ))),
+    }
+}
+
+// Check an update of a field the API requires, which may change but not go
+// away
+pub(crate) fn required_patch(field: &str, patch: &Patch<String>) -> Result<(), SwarmNodeError> {
+    match patch {
+        Patch::Unchanged => Ok(()),
+        Patch::Set(value) => required(field, Some(value)),
+        Patch::Clear => Err(SwarmNodeError::InvalidArgument(format!(
+            "{} cannot be cleared",
+            field
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Update {
+        #[serde(skip_serializing_if = "Patch::is_unchanged")]
+        a: Patch<String>,
+        #[serde(skip_serializing_if = "Patch::is_unchanged")]
+        b: Patch<String>,
+        #[serde(skip_serializing_if = "Patch::is_unchanged")]
+        c: Patch<u32>,
+    }
+
+    #[test]
+    fn each_state_serializes_differently() {
+        let update = Update {
+            a: Patch::Set("x".to_string()),
+            b: Patch::Clear,
+            c: Patch::Unchanged,
+        };
+        assert_eq!(
+            serde_json::to_string(&update).unwrap(),
+            r#"{"a":"x","b":null}"#
+        );
+
+        let update = Update {
+            a: Patch::Unchanged,
+            b: Patch::Unchanged,
+            c: Patch::Set(0),
+        };
+        assert_eq!(serde_json::to_string(&update).unwrap(), r#"{"c":0}"#);
+    }
+
+    #[test]
+    fn required_patch_rejects_clear_and_blank_values() {
+        assert!(required_patch("name", &Patch::Unchanged).is_ok());
+        assert!(required_patch("name", &Patch::Set("x".to_string())).is_ok());
+
+        for patch in [
+            Patch::Clear,
+            Patch::Set(String::new()),
+            Patch::Set(" \n".to_string()),
+        ] {
+            let error = required_patch("name", &patch).unwrap_err();
+            assert!(
+                matches!(&error, SwarmNodeError::InvalidArgument(message) if message.starts_with("name ")),
+                "{:?}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn required_reports_missing_and_blank_values() {
+        assert!(required("script", Some("x")).is_ok());
+        let message = |value| required("script", value).unwrap_err().to_string();
+        assert!(message(None).contains("script is required"));
+        assert!(message(Some("  ")).contains("script must not be empty"));
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::time::SystemTime;
+
+use super::error::SwarmNodeError;
+use super::pagination::page_size_param;
+use super::time::to_rfc3339;
+
+// Resources listed by page number rather than by cursor
+pub trait PageNumbered {}
+
+// Filters, ordering and extra query parameters of a list call on resource
+// `R`. Filters that only make sense for some resources are added next to
+// those resources, e.g. `ListQuery::<Execution>::status`.
+pub struct ListQuery<R> {
+    params: HashMap<String, String>,
+    page: Option<u32>,
+    page_size: Option<u32>,
+    resource: PhantomData<fn() -> R>,
+}
+
+impl<R> ListQuery<R> {
+    pub fn new() -> Self {
+        ListQuery {
+            params: HashMap::new(),
+            page: None,
+            page_size: None,
+            resource: PhantomData,
+        }
+    }
+
+    // Only resources created at or after `time`. Takes a `SystemTime` or,
+    // with the `chrono` feature, a `Timestamp`.
+    pub fn created_after(self, time: impl Into<SystemTime>) -> Self {
+        self.param("created_after", to_rfc3339(time.into()))
+    }
+
+    // Only resources created at or before `time`
+    pub fn created_before(self, time: impl Into<SystemTime>) -> Self {
+        self.param("created_before", to_rfc3339(time.into()))
+    }
+
+    // Sort by `field` in ascending order
+    pub fn order_by(self, field: &str) -> Self {
+        self.param("ordering", field)
+    }
+
+    // Sort by `field` in descending order
+    pub fn order_by_desc(self, field: &str) -> Self {
+        self.param("ordering", format!("-{}", field))
+    }
+
+    // Any other query parameter, sent as is
+    pub fn param(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.params.insert(key.into(), value.into());
+        self
+    }
+
+    pub(crate) fn params(&self) -> HashMap<String, String> {
+        self.params.clone()
+    }
+}
+
+impl<R: PageNumbered> ListQuery<R> {
+    pub(crate) fn paged(page: Option<u32>, page_size: Option<u32>) -> Self {
+        ListQuery {
+            page,
+            page_size,
+            ..ListQuery::new()
+        }
+    }
+
+    // Page to fetch, counting from 1
+    pub fn page(mut self, page: u32) -> Self {
+        self.page = Some(page);
+        self
+    }
+
+    // Results per page, at most the client's `max_page_size` when one is set
+    pub fn page_size(mut self, page_size: u32) -> Self {
+        self.page_size = Some(page_size);
+        self
+    }
+
+    pub(crate) fn page_params(
+        &self,
+        max_page_size: Option<u32>,
+    ) -> Result<HashMap<String, String>, SwarmNodeError> {
+        let mut params = self.params();
+        params.insert("page".to_string(), self.page.unwrap_or(1).to_string());
+        params.insert(
+            "page_size".to_string(),
+            page_size_param(self.page_size, max_page_size)?,
+        );
+        Ok(params)
+    }
+}
+
+impl<R> Default for ListQuery<R> {
+    fn default() -> Self {
+        ListQuery::new()
+    }
+}
+
+impl<R> Clone for ListQuery<R> {
+    fn clone(&self) -> Self {
+        ListQuery {
+            params: self.params.clone(),
+            page: self.page,
+            page_size: self.page_size,
+            resource: PhantomData,
+        }
+    }
+}
+
+impl<R> fmt::Debug for ListQuery<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ListQuery")
+            .field("resource", &std::any::type_name::<R>())
+            .field("params", &self.params)
+            .field("page", &self.page)
+            .field("page_size", &self.page_size)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    struct Resource;
+    impl PageNumbered for Resource {}
+
+    #[test]
+    fn date_range_is_sent_as_rfc3339() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_714_564_800);
+        let params = ListQuery::<Resource>::new()
+            .created_after(start)
+            .created_before(start + Duration::from_secs(86_400))
+            .params();
+        assert_eq!(params["created_after"], "2024-05-01T12:00:00.000000Z");
+        assert_eq!(params["created_before"], "2024-05-02T12:00:00.000000Z");
+    }
+
+    #[test]
+    fn page_params_check_the_page_size() {
+        let query = ListQuery::<Resource>::new().order_by_desc("created");
+        let params = query.page_params(None).unwrap();
+        assert_eq!(params["ordering"], "-created");
+        assert_eq!(params["page"], "1");
+        assert_eq!(params["page_size"], "10");
+
+        let query = query.page(3).page_size(500);
+        assert_eq!(query.page_params(None).unwrap()["page_size"], "500");
+        assert!(query.page_params(Some(100)).is_err());
+        assert!(ListQuery::<Resource>::new()
+            .page_size(0)
+            .page_params(None)
+            .is_err());
+    }
+}
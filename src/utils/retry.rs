@@ -0,0 +1,177 @@
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Method, StatusCode};
+use std::time::{Duration, SystemTime};
+
+use super::error::SwarmNodeError;
+
+// How failed requests are retried. `max_attempts` counts the first attempt,
+// so `max_attempts: 1` disables retries entirely.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    // Longest wait between two attempts, also when `Retry-After` asks for
+    // more
+    pub max_delay: Duration,
+    // Fraction of each backoff delay that is randomized, between 0.0 and 1.0
+    pub jitter: f64,
+    // Upper bound on the time spent across all attempts, including waits
+    pub deadline: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(10),
+            jitter: 0.5,
+            deadline: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Policy that makes exactly one attempt
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    // Delay before attempt `attempt + 1`, growing exponentially from
+    // `base_delay` and capped at `max_delay`
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self
+            .base_delay
+            .saturating_mul(1u32 << exponent)
+            .min(self.max_delay);
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        delay.mul_f64(1.0 - jitter * fastrand::f64())
+    }
+
+    // Whether `error` is worth another attempt. Requests that are not
+    // idempotent are only retried when the server cannot have acted on them:
+    // the connection was never established, or the request was throttled.
+    pub fn should_retry(&self, error: &SwarmNodeError, idempotent: bool) -> bool {
+        if idempotent {
+            return error.is_retryable();
+        }
+
+        match error {
+            SwarmNodeError::Http(err) => err.is_connect(),
+            _ => error.status() == Some(StatusCode::TOO_MANY_REQUESTS),
+        }
+    }
+}
+
+pub fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE
+    )
+}
+
+// Parse a `Retry-After` header given either in seconds or as an HTTP date
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::error::ApiError;
+    use reqwest::header::HeaderValue;
+
+    fn policy(jitter: f64) -> RetryPolicy {
+        RetryPolicy {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            jitter,
+            ..Default::default()
+        }
+    }
+
+    fn headers(retry_after: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(retry_after).unwrap());
+        headers
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = policy(0.0);
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(4), Duration::from_millis(800));
+        assert_eq!(policy.backoff(5), Duration::from_secs(1));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn backoff_jitter_only_shortens_the_delay() {
+        for _ in 0..100 {
+            let delay = policy(0.5).backoff(3);
+            assert!(delay <= Duration::from_millis(400), "{:?}", delay);
+            assert!(delay >= Duration::from_millis(200), "{:?}", delay);
+        }
+
+        // Out of range jitter is clamped rather than producing a negative
+        // factor
+        assert!(policy(5.0).backoff(3) <= Duration::from_millis(400));
+    }
+
+    #[test]
+    fn retry_after_in_seconds() {
+        assert_eq!(retry_after(&headers("120")), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after(&headers(" 3 ")), Some(Duration::from_secs(3)));
+        assert_eq!(retry_after(&headers("0")), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_after_as_http_date() {
+        let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(30));
+        let delay = retry_after(&headers(&date)).unwrap();
+        assert!(delay <= Duration::from_secs(30), "{:?}", delay);
+        assert!(delay >= Duration::from_secs(28), "{:?}", delay);
+
+        // A date in the past means retry right away
+        assert_eq!(
+            retry_after(&headers("Sun, 06 Nov 1994 08:49:37 GMT")),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn retry_after_ignores_missing_and_malformed_values() {
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+        assert_eq!(retry_after(&headers("-1")), None);
+        assert_eq!(retry_after(&headers("1.5")), None);
+        assert_eq!(retry_after(&headers("tomorrow")), None);
+    }
+
+    #[test]
+    fn non_idempotent_requests_are_only_retried_when_throttled() {
+        let error = |status| SwarmNodeError::from(ApiError::new(status, &HeaderMap::new(), b""));
+        let policy = RetryPolicy::default();
+
+        assert!(policy.should_retry(&error(StatusCode::BAD_GATEWAY), true));
+        assert!(!policy.should_retry(&error(StatusCode::BAD_GATEWAY), false));
+        assert!(policy.should_retry(&error(StatusCode::TOO_MANY_REQUESTS), false));
+        assert!(!policy.should_retry(&error(StatusCode::BAD_REQUEST), true));
+    }
+}
@@ -0,0 +1,81 @@
+// Helpers for tests that run requests through a client without touching the
+// network
+
+use reqwest::header::HeaderMap;
+use reqwest::{Method, Url};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::client::SwarmNodeClient;
+use super::config::SwarmNodeConfig;
+use super::middleware::{Middleware, OutgoingRequest, SyntheticResponse};
+use super::retry::RetryPolicy;
+
+pub(crate) const BASE_URL: &str = "http://api.test";
+
+// A request as `FakeApi` saw it
+#[derive(Debug, Clone)]
+pub(crate) struct Seen {
+    pub method: Method,
+    pub url: Url,
+    pub headers: HeaderMap,
+    pub attempt: u32,
+}
+
+type Handler = dyn Fn(&Seen) -> SyntheticResponse + Send + Sync;
+
+// Middleware that answers every request with `handler` and records it
+pub(crate) struct FakeApi {
+    handler: Box<Handler>,
+    seen: Mutex<Vec<Seen>>,
+}
+
+impl FakeApi {
+    pub fn new(handler: impl Fn(&Seen) -> SyntheticResponse + Send + Sync + 'static) -> Arc<Self> {
+        Arc::new(FakeApi {
+            handler: Box::new(handler),
+            seen: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn seen(&self) -> Vec<Seen> {
+        self.seen.lock().unwrap().clone()
+    }
+}
+
+impl Middleware for FakeApi {
+    fn on_request(&self, request: &mut OutgoingRequest<'_>) -> Option<SyntheticResponse> {
+        let seen = Seen {
+            method: request.method.clone(),
+            url: request.url.clone(),
+            headers: request.headers.clone(),
+            attempt: request.attempt,
+        };
+        let response = (self.handler)(&seen);
+        self.seen.lock().unwrap().push(seen);
+        Some(response)
+    }
+}
+
+// Retries without waiting, so that retry tests run fast
+pub(crate) fn fast_retries(max_attempts: u32) -> RetryPolicy {
+    RetryPolicy {
+        max_attempts,
+        base_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(5),
+        jitter: 0.0,
+        deadline: None,
+    }
+}
+
+// Client for `BASE_URL` whose calls go through `middleware`
+pub(crate) fn client(middleware: Vec<Arc<dyn Middleware>>, retry: RetryPolicy) -> SwarmNodeClient {
+    SwarmNodeClient::new(SwarmNodeConfig {
+        api_key: Some("test-key".to_string()),
+        base_url: Some(BASE_URL.to_string()),
+        retry,
+        middleware,
+        ..Default::default()
+    })
+    .unwrap()
+}
@@ -0,0 +1,85 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// 9999-12-31T23:59:59Z, the last instant RFC 3339 can express
+const MAX_RFC3339_SECS: u64 = 253_402_300_799;
+
+// Date-time returned by the API. A `chrono::DateTime<Utc>` with the `chrono`
+// feature, the RFC 3339 string as sent by the API without it.
+#[cfg(feature = "chrono")]
+pub type Timestamp = chrono::DateTime<chrono::Utc>;
+#[cfg(not(feature = "chrono"))]
+pub type Timestamp = String;
+
+#[cfg(feature = "chrono")]
+pub fn to_system_time(timestamp: &Timestamp) -> Option<SystemTime> {
+    Some(SystemTime::from(*timestamp))
+}
+
+// Without `chrono` only UTC date-times, the format the API uses, are read
+#[cfg(not(feature = "chrono"))]
+pub fn to_system_time(timestamp: &Timestamp) -> Option<SystemTime> {
+    let timestamp = match timestamp.strip_suffix("+00:00") {
+        Some(utc) => format!("{}Z", utc),
+        None => timestamp.to_string(),
+    };
+    humantime::parse_rfc3339(&timestamp).ok()
+}
+
+// `time` as an RFC 3339 UTC date-time with microseconds, the precision the
+// API stores, e.g. `2024-05-01T12:00:00.000000Z`. Times outside the range
+// RFC 3339 can express are clamped to it.
+pub fn to_rfc3339(time: SystemTime) -> String {
+    let max = UNIX_EPOCH + Duration::from_secs(MAX_RFC3339_SECS);
+    humantime::format_rfc3339_micros(time.clamp(UNIX_EPOCH, max)).to_string()
+}
+
+// Time from `start` to `end`, `None` when either cannot be read or `end`
+// comes first
+pub fn between(start: &Timestamp, end: &Timestamp) -> Option<Duration> {
+    to_system_time(end)?
+        .duration_since(to_system_time(start)?)
+        .ok()
+}
+
+// Time elapsed since `timestamp`, `None` when it cannot be read or lies in
+// the future
+pub fn elapsed_since(timestamp: &Timestamp) -> Option<Duration> {
+    to_system_time(timestamp)?.elapsed().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_rfc3339_with_microseconds() {
+        let time = UNIX_EPOCH + Duration::new(1_714_564_800, 123_456_789);
+        assert_eq!(to_rfc3339(time), "2024-05-01T12:00:00.123456Z");
+        assert_eq!(to_rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000000Z");
+    }
+
+    #[test]
+    fn clamps_times_rfc3339_cannot_express() {
+        assert_eq!(
+            to_rfc3339(UNIX_EPOCH - Duration::from_secs(1)),
+            "1970-01-01T00:00:00.000000Z"
+        );
+        assert_eq!(
+            to_rfc3339(UNIX_EPOCH + Duration::from_secs(MAX_RFC3339_SECS * 2)),
+            "9999-12-31T23:59:59.000000Z"
+        );
+    }
+
+    #[cfg(not(feature = "chrono"))]
+    #[test]
+    fn reads_utc_timestamps_in_either_notation() {
+        let expected = UNIX_EPOCH + Duration::new(1_714_564_800, 500_000_000);
+        for timestamp in ["2024-05-01T12:00:00.5Z", "2024-05-01T12:00:00.500000+00:00"] {
+            assert_eq!(to_system_time(&timestamp.to_string()), Some(expected));
+        }
+        assert_eq!(
+            to_system_time(&"2024-05-01T14:00:00+02:00".to_string()),
+            None
+        );
+    }
+}
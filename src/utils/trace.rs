@@ -0,0 +1,224 @@
+// `tracing` instrumentation, compiled to no-ops unless the `tracing` feature
+// is enabled.
+//
+// Only methods, URL paths, status codes, timings and attempt numbers are
+// recorded. Request and response bodies are never recorded, so payloads and
+// agent env vars cannot leak, and headers go through `redact_headers` which
+// masks `Authorization` and any other header marked sensitive.
+
+use reqwest::header::HeaderMap;
+use reqwest::{Method, StatusCode, Url};
+use std::future::Future;
+use std::time::Duration;
+
+use super::error::SwarmNodeError;
+
+// Resource class of an API URL: the path segment following the API version,
+// e.g. `agents` for `/v1/agents/<id>/`
+#[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+pub(crate) fn resource_of(url: &Url, api_version: &str) -> String {
+    let version = api_version.trim_matches('/');
+    let mut segments = url.path_segments().into_iter().flatten();
+    segments
+        .by_ref()
+        .find(|segment| *segment == version)
+        .and_then(|_| segments.next())
+        .unwrap_or_default()
+        .to_string()
+}
+
+// Headers as name/value pairs, with `Authorization` and sensitive values
+// masked
+#[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+pub(crate) fn redact_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if value.is_sensitive() || name == reqwest::header::AUTHORIZATION {
+                "<redacted>".to_string()
+            } else {
+                value.to_str().unwrap_or("<binary>").to_string()
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+#[cfg(feature = "tracing")]
+mod imp {
+    use super::*;
+    use tracing::field::Empty;
+    use tracing::Instrument;
+
+    pub(crate) type Span = tracing::Span;
+
+    pub(crate) fn request_span(method: &Method, url: &Url, api_version: &str) -> Span {
+        tracing::info_span!(
+            "swarmnode.request",
+            method = %method,
+            path = url.path(),
+            resource = %resource_of(url, api_version),
+            status = Empty,
+            latency_ms = Empty,
+            attempts = Empty,
+        )
+    }
+
+    pub(crate) fn websocket_span(url: &Url, api_version: &str) -> Span {
+        tracing::info_span!(
+            "swarmnode.websocket",
+            path = url.path(),
+            resource = %resource_of(url, api_version),
+            status = Empty,
+            latency_ms = Empty,
+            attempts = Empty,
+        )
+    }
+
+    pub(crate) async fn instrument<F: Future>(span: &Span, future: F) -> F::Output {
+        future.instrument(span.clone()).await
+    }
+
+    pub(crate) fn attempt_started(attempt: u32, headers: &HeaderMap) {
+        tracing::trace!(attempt, headers = ?redact_headers(headers), "sending request");
+    }
+
+    pub(crate) fn attempt_finished(
+        attempt: u32,
+        status: Option<StatusCode>,
+        error: Option<&SwarmNodeError>,
+        elapsed: Duration,
+    ) {
+        let span = Span::current();
+        span.record("attempts", attempt);
+        if let Some(status) = status {
+            span.record("status", status.as_u16());
+        }
+
+        tracing::debug!(
+            attempt,
+            status = status.map(|status| status.as_u16()),
+            latency_ms = elapsed.as_millis() as u64,
+            error = error.map(tracing::field::display),
+            "attempt finished"
+        );
+    }
+
+    pub(crate) fn retrying(attempt: u32, delay: Duration, error: &SwarmNodeError) {
+        tracing::warn!(
+            attempt,
+            delay_ms = delay.as_millis() as u64,
+            error = %error,
+            "retrying request"
+        );
+    }
+
+    pub(crate) fn finished<T>(span: &Span, result: &Result<T, SwarmNodeError>, elapsed: Duration) {
+        span.record("latency_ms", elapsed.as_millis() as u64);
+        if let Err(error) = result {
+            if let Some(status) = error.status() {
+                span.record("status", status.as_u16());
+            }
+            span.in_scope(|| tracing::warn!(error = %error, "request failed"));
+        }
+    }
+
+    pub(crate) fn message_received(span: &Span, len: usize) {
+        span.in_scope(|| tracing::debug!(len, "message received"));
+    }
+
+    pub(crate) fn session_closed(span: &Span) {
+        span.in_scope(|| tracing::debug!("session closed"));
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod imp {
+    use super::*;
+
+    #[derive(Clone)]
+    pub(crate) struct Span;
+
+    pub(crate) fn request_span(_method: &Method, _url: &Url, _api_version: &str) -> Span {
+        Span
+    }
+
+    pub(crate) fn websocket_span(_url: &Url, _api_version: &str) -> Span {
+        Span
+    }
+
+    pub(crate) async fn instrument<F: Future>(_span: &Span, future: F) -> F::Output {
+        future.await
+    }
+
+    pub(crate) fn attempt_started(_attempt: u32, _headers: &HeaderMap) {}
+
+    pub(crate) fn attempt_finished(
+        _attempt: u32,
+        _status: Option<StatusCode>,
+        _error: Option<&SwarmNodeError>,
+        _elapsed: Duration,
+    ) {
+    }
+
+    pub(crate) fn retrying(_attempt: u32, _delay: Duration, _error: &SwarmNodeError) {}
+
+    pub(crate) fn finished<T>(
+        _span: &Span,
+        _result: &Result<T, SwarmNodeError>,
+        _elapsed: Duration,
+    ) {
+    }
+
+    pub(crate) fn message_received(_span: &Span, _len: usize) {}
+
+    pub(crate) fn session_closed(_span: &Span) {}
+}
+
+pub(crate) use imp::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+
+    #[test]
+    fn authorization_and_sensitive_headers_are_redacted() {
+        let mut headers = HeaderMap::new();
+        // Not marked sensitive, still redacted
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer secret"));
+        let mut token = HeaderValue::from_static("secret");
+        token.set_sensitive(true);
+        headers.insert("x-token", token);
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert("x-raw", HeaderValue::from_bytes(b"\xff").unwrap());
+
+        let mut redacted = redact_headers(&headers);
+        redacted.sort();
+        assert_eq!(
+            redacted,
+            [
+                ("authorization".to_string(), "<redacted>".to_string()),
+                ("content-type".to_string(), "application/json".to_string()),
+                ("x-raw".to_string(), "<binary>".to_string()),
+                ("x-token".to_string(), "<redacted>".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn resource_is_the_segment_after_the_version() {
+        let resource = |url: &str, version: &str| resource_of(&Url::parse(url).unwrap(), version);
+        assert_eq!(resource("https://api.test/v1/agents/abc/", "v1"), "agents");
+        assert_eq!(
+            resource("https://api.test/proxy/v2/stores/", "/v2/"),
+            "stores"
+        );
+        assert_eq!(
+            resource("wss://api.test/ws/v1/execution-stream/abc/", "v1"),
+            "execution-stream"
+        );
+        assert_eq!(resource("https://api.test/v1/", "v1"), "");
+        assert_eq!(resource("https://api.test/agents/", "v1"), "");
+    }
+}